@@ -0,0 +1,81 @@
+//! Times how fast a log format parses a log file, with our tokenizer and with a regex.
+//!
+//!     cargo run --release --example bench -- www.access.log [FORMAT]
+//!
+//! FORMAT is an Apache `LogFormat` string or nickname, "combined" by default.
+
+// We borrow modules from the webtop binary, of which we only use a part.
+#![allow(dead_code)]
+
+extern crate regex;
+extern crate time;
+extern crate rustc_serialize;
+
+#[path = "../src/hit.rs"]
+mod hit;
+#[path = "../src/parse.rs"]
+mod parse;
+#[path = "../src/json_parse.rs"]
+mod json_parse;
+#[path = "../src/token_parse.rs"]
+mod token_parse;
+#[path = "../src/line_buffer.rs"]
+mod line_buffer;
+
+use std::io::prelude::*;
+use std::env;
+use std::fs;
+use std::path::Path;
+use time::precise_time_s;
+use parse::{LogParser, Parser};
+use line_buffer::decode_lines;
+
+/// Parses all `lines` with `parser` and returns how many were parsed and how long it took.
+fn time_parser(parser: &Parser, lines: &[&str]) -> (usize, f64) {
+    let started_at = precise_time_s();
+    let parsed_count = lines.iter().filter(|line| parser.parse_line(line).is_some()).count();
+    (parsed_count, precise_time_s() - started_at)
+}
+
+/// Parses the log file at `path`, a benchmark corpus, with `format` matched by our tokenizer and
+/// then by a regex, and prints how fast each of them went.
+fn run_benchmark(path: &Path, format: &str) -> Result<(), String> {
+    let tokenizer = match Parser::from_apache_format(format) {
+        Ok(parser) => parser,
+        Err(e) => return Err(e),
+    };
+    let regex = match Parser::regex_from_apache_format(format) {
+        Ok(parser) => parser,
+        Err(e) => return Err(e),
+    };
+    let mut contents = Vec::new();
+    if let Err(e) = fs::File::open(path).and_then(|mut fp| fp.read_to_end(&mut contents)) {
+        return Err(format!("Can't read {}! Error: {}", path.display(), e));
+    }
+    let (contents, _) = decode_lines(contents);
+    let lines: Vec<&str> = contents.lines().filter(|l| !l.trim().is_empty()).collect();
+    println!("Parsing {} lines of {} as \"{}\"", lines.len(), path.display(), format);
+    for &(name, ref parser) in [("tokenizer", &tokenizer), ("regex", &regex)].iter() {
+        let (parsed_count, duration) = time_parser(parser, &lines);
+        println!(
+            "{:>10}: {} lines parsed in {:.2}s, {:.0} lines/s",
+            name, parsed_count, duration, lines.len() as f64 / duration
+        );
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let path = match args.get(1) {
+        Some(path) => path,
+        None => {
+            println!("Usage: {} FILE [FORMAT]", args[0]);
+            return;
+        }
+    };
+    let format = args.get(2).map(|s| &s[..]).unwrap_or("combined");
+    if let Err(e) = run_benchmark(Path::new(path), format) {
+        println!("{}", e);
+    }
+}
@@ -0,0 +1,182 @@
+use std::io::prelude::*;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Child, ChildStdout, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use time::precise_time_s;
+use libc;
+use input::{InputMsg, InputSender, push_line_msgs, flush_line_msgs};
+use line_buffer::LineBuffer;
+
+// How long we wait before restarting a command that ended, in seconds. It doubles each time the
+// command ends early, up to the max.
+const MIN_RESTART_DELAY: u32 = 1;
+const MAX_RESTART_DELAY: u32 = 60;
+
+// A command that ran for that long, in seconds, was working. If it ends, we start over with the
+// shortest delay.
+const HEALTHY_RUN_TIME: f64 = 60.0;
+
+// How long we give a command to end when we ask it to, in seconds, before killing it
+const TERMINATION_GRACE_PERIOD: f64 = 1.0;
+
+// How much we read from a command's output at once, at most
+const READ_SIZE: usize = 65536;
+
+struct CommandState {
+    child: Option<Child>,
+    stopped: bool,
+}
+
+/// A command we run, and restart, to read logs from its output.
+///
+/// The command runs in its own process group so that when we stop it, whatever it started
+/// (the command line is run by `sh`) is stopped as well.
+///
+pub struct CommandSource {
+    state: Arc<Mutex<CommandState>>,
+}
+
+/// Sends `signal` to the process group of `child`.
+fn signal_group(child: &Child, signal: libc::c_int) {
+    unsafe { libc::funcs::posix88::signal::kill(-(child.id() as libc::pid_t), signal) };
+}
+
+fn start(command: &str, state: &Arc<Mutex<CommandState>>) -> Option<io::Result<ChildStdout>> {
+    let mut state = state.lock().unwrap();
+    if state.stopped {
+        return None;
+    }
+    // Our terminal belongs to curses: the command can't read from it or write errors to it.
+    let spawned = Command::new("sh").arg("-c").arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn();
+    Some(spawned.map(|mut child| {
+        let stdout = child.stdout.take().unwrap();
+        state.child = Some(child);
+        stdout
+    }))
+}
+
+/// Waits for our command to end and returns a description of how it did.
+///
+/// We don't hold the lock while waiting so that we can be stopped.
+///
+fn wait_for_end(state: &Arc<Mutex<CommandState>>) -> Option<String> {
+    loop {
+        {
+            let mut state = state.lock().unwrap();
+            if state.stopped {
+                return None;
+            }
+            let ended = match state.child {
+                Some(ref mut child) => child.try_wait(),
+                None => return Some("ended".to_string()),
+            };
+            match ended {
+                Ok(Some(status)) => {
+                    state.child = None;
+                    return Some(format!("ended ({})", status));
+                },
+                Ok(None) => (),
+                Err(e) => {
+                    state.child = None;
+                    return Some(format!("ended (error: {})", e));
+                },
+            }
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+impl CommandSource {
+    /// Runs `command` in a thread and sends the lines it outputs through `tx`, under the `site`
+    /// name. When the command ends, it's restarted after a delay.
+    pub fn spawn(command: &str, site: String, tx: InputSender) -> CommandSource {
+        let state = Arc::new(Mutex::new(CommandState { child: None, stopped: false }));
+        let thread_state = state.clone();
+        let command = command.to_string();
+        thread::spawn(move || {
+            let state = thread_state;
+            let mut delay = MIN_RESTART_DELAY;
+            loop {
+                let started_at = precise_time_s();
+                let ended = match start(&command, &state) {
+                    None => return,
+                    Some(Ok(mut stdout)) => {
+                        let mut line_buffer = LineBuffer::new();
+                        let mut buf = vec![0u8; READ_SIZE];
+                        loop {
+                            let read_size = match stdout.read(&mut buf) {
+                                Ok(0) => break,
+                                Ok(read_size) => read_size,
+                                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                                Err(_) => break,
+                            };
+                            let mut msgs = Vec::new();
+                            push_line_msgs(&site, &mut line_buffer, &buf[..read_size], &mut msgs);
+                            if !tx.send_all(msgs) {
+                                return;
+                            }
+                        }
+                        let mut msgs = Vec::new();
+                        flush_line_msgs(&site, &mut line_buffer, &mut msgs);
+                        if !tx.send_all(msgs) {
+                            return;
+                        }
+                        match wait_for_end(&state) {
+                            Some(ended) => ended,
+                            None => return,
+                        }
+                    },
+                    Some(Err(e)) => format!("couldn't start (error: {})", e),
+                };
+                if precise_time_s() - started_at >= HEALTHY_RUN_TIME {
+                    delay = MIN_RESTART_DELAY;
+                }
+                let msg = format!("`{}` {}, restarting it in {}s.", command, ended, delay);
+                if !tx.send(InputMsg::Note(msg, true)) {
+                    return;
+                }
+                thread::sleep(Duration::from_secs(delay as u64));
+                if delay < MAX_RESTART_DELAY {
+                    delay = if delay * 2 > MAX_RESTART_DELAY { MAX_RESTART_DELAY } else { delay * 2 };
+                }
+                if !tx.send(InputMsg::Note(format!("Restarted `{}`.", command), false)) {
+                    return;
+                }
+            }
+        });
+        CommandSource {
+            state: state,
+        }
+    }
+
+    /// Stops our command, and doesn't restart it.
+    ///
+    /// We first ask it nicely, then kill it if it doesn't end in time.
+    ///
+    pub fn stop(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.stopped = true;
+        let mut child = match state.child.take() {
+            Some(child) => child,
+            None => return,
+        };
+        signal_group(&child, libc::SIGTERM);
+        let deadline = precise_time_s() + TERMINATION_GRACE_PERIOD;
+        while precise_time_s() < deadline {
+            match child.try_wait() {
+                Ok(Some(_)) | Err(_) => return,
+                Ok(None) => thread::sleep(Duration::from_millis(20)),
+            }
+        }
+        signal_group(&child, libc::SIGKILL);
+        let _ = child.wait();
+    }
+}
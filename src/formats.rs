@@ -0,0 +1,151 @@
+use std::sync::Arc;
+use parse::{LogParser, SharedParser, Parser, NGINX_MAIN_FORMAT};
+use self::haproxy::HaproxyParser;
+use self::traefik::TraefikParser;
+use self::squid::SquidParser;
+use self::elb::ElbParser;
+use self::cloudfront::CloudfrontParser;
+use self::s3::S3Parser;
+use self::gcs::GcsParser;
+
+// Parsers of the formats that aren't plain Apache or nginx formats, one module each in
+// src/formats/
+mod haproxy;
+mod traefik;
+mod squid;
+mod elb;
+mod cloudfront;
+mod s3;
+mod gcs;
+
+// How many lines we look at, at most, when detecting the log format
+const MAX_DETECTION_LINES: usize = 50;
+
+/// A log format we know by name.
+pub struct Format {
+    pub name: &'static str,
+    // Whether we try it when detecting the log format
+    pub detected: bool,
+    create: fn() -> Result<SharedParser, String>,
+}
+
+fn share<P: LogParser + 'static>(parser: Result<P, String>) -> Result<SharedParser, String> {
+    parser.map(|parser| Arc::new(parser) as SharedParser)
+}
+
+fn json() -> Result<SharedParser, String> {
+    share(Parser::from_json_mapping(None))
+}
+
+fn haproxy() -> Result<SharedParser, String> {
+    share(Ok(HaproxyParser))
+}
+
+fn squid() -> Result<SharedParser, String> {
+    share(Ok(SquidParser))
+}
+
+fn traefik() -> Result<SharedParser, String> {
+    share(TraefikParser::new())
+}
+
+fn elb() -> Result<SharedParser, String> {
+    share(Ok(ElbParser))
+}
+
+fn cloudfront() -> Result<SharedParser, String> {
+    share(Ok(CloudfrontParser))
+}
+
+fn s3() -> Result<SharedParser, String> {
+    share(Ok(S3Parser))
+}
+
+fn gcs() -> Result<SharedParser, String> {
+    share(Ok(GcsParser))
+}
+
+fn vhost_combined() -> Result<SharedParser, String> {
+    share(Parser::from_apache_format("vhost_combined"))
+}
+
+fn nginx_main() -> Result<SharedParser, String> {
+    share(Parser::from_nginx_format(NGINX_MAIN_FORMAT))
+}
+
+fn combined() -> Result<SharedParser, String> {
+    share(Parser::from_apache_format("combined"))
+}
+
+fn common() -> Result<SharedParser, String> {
+    share(Parser::from_apache_format("common"))
+}
+
+/// The formats we know by name.
+///
+/// When detecting the log format, we try them in this order, which goes from the most specific
+/// to the least specific. "nginx_main" is the format in nginx's default configuration, "haproxy"
+/// is HAProxy's HTTP format, "traefik" is Traefik's common format and "squid" is Squid's native
+/// format. "elb" is AWS's load balancer format, for Application Load Balancers (also known as
+/// "alb") as well as Classic ones, "cloudfront" is CloudFront's standard format, "s3" is S3's
+/// server access format and "gcs" is Google Cloud Storage's usage format.
+///
+/// To add a format, implement `LogParser` for it in its own module and add it here.
+///
+pub const FORMATS: [Format; 13] = [
+    Format { name: "json", detected: true, create: json },
+    Format { name: "haproxy", detected: true, create: haproxy },
+    Format { name: "squid", detected: true, create: squid },
+    Format { name: "traefik", detected: true, create: traefik },
+    Format { name: "elb", detected: true, create: elb },
+    Format { name: "alb", detected: false, create: elb },
+    Format { name: "cloudfront", detected: true, create: cloudfront },
+    Format { name: "s3", detected: true, create: s3 },
+    Format { name: "gcs", detected: true, create: gcs },
+    Format { name: "vhost_combined", detected: true, create: vhost_combined },
+    Format { name: "nginx_main", detected: true, create: nginx_main },
+    Format { name: "combined", detected: true, create: combined },
+    Format { name: "common", detected: true, create: common },
+];
+
+pub fn format_names() -> Vec<&'static str> {
+    FORMATS.iter().map(|format| format.name).collect()
+}
+
+/// Returns a parser for the format named `name`, if we know it.
+pub fn parser_by_name(name: &str) -> Option<SharedParser> {
+    FORMATS.iter().find(|format| format.name == name).and_then(|format| (format.create)().ok())
+}
+
+/// Returns a parser for `format`, which is either the name of a format we know or an Apache
+/// `LogFormat` string, along with the name we show for it.
+pub fn parser_for(format: &str) -> Result<(SharedParser, String), String> {
+    match parser_by_name(format) {
+        Some(parser) => Ok((parser, format.to_string())),
+        None => share(Parser::from_apache_format(format)).map(|parser| (parser, "custom".to_string())),
+    }
+}
+
+/// Returns the format that fully parses the most of `lines`, along with its name.
+///
+/// On a tie, the most specific format wins. Returns `None` if no format parses any line.
+///
+pub fn detect_format(lines: &[String]) -> Option<(&'static str, SharedParser)> {
+    let mut best: Option<(&'static str, SharedParser)> = None;
+    let mut best_score = 0;
+    for format in FORMATS.iter().filter(|format| format.detected) {
+        let parser = match (format.create)() {
+            Ok(parser) => parser,
+            Err(_) => continue,
+        };
+        let score = lines.iter()
+            .take(MAX_DETECTION_LINES)
+            .filter(|line| parser.parses_fully(line))
+            .count();
+        if score > best_score {
+            best = Some((format.name, parser));
+            best_score = score;
+        }
+    }
+    best
+}
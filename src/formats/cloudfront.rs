@@ -0,0 +1,89 @@
+use std::str::FromStr;
+use time::strptime;
+use hit::{Hit, normalize_host};
+use parse::{LogParser, to_utc, set_path_and_query};
+
+/// Returns `value` without its "%XX" escapes.
+///
+/// CloudFront escapes spaces and other special characters in the referer and the user agent.
+/// Escapes that don't stand for printable ASCII are kept as they are.
+///
+fn percent_decode(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(index) = rest.find('%') {
+        result.push_str(&rest[..index]);
+        let hex = rest[index+1..].chars().take(2).collect::<String>();
+        match u8::from_str_radix(&hex, 16) {
+            Ok(byte) if hex.len() == 2 && byte >= 0x20 && byte < 0x7f => {
+                result.push(byte as char);
+                rest = &rest[index+3..];
+            },
+            _ => {
+                result.push('%');
+                rest = &rest[index+1..];
+            },
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// A parser for CloudFront standard logs, whose fields are separated by tabs.
+///
+/// Fields are the ones of the "#Fields:" header line, which we skip along with the "#Version:" one.
+/// We need those up to `time-taken`. Times are in UTC.
+///
+pub struct CloudfrontParser;
+
+impl LogParser for CloudfrontParser {
+    fn try_parse_line(&self, line: &str) -> Result<Hit, String> {
+        let fields: Vec<&str> = line.trim_end_matches(|c| c == '\r' || c == '\n').split('\t').collect();
+        if fields.len() < 19 {
+            return Err("Missing fields".to_string());
+        }
+        let mut hit = Hit::new();
+        let date_time = format!("{} {}", fields[0], fields[1]);
+        hit.time = match strptime(&date_time, "%Y-%m-%d %H:%M:%S") {
+            Ok(tm) => to_utc(tm, Some(0)),
+            Err(_) => return Err(format!("Invalid time \"{}\"", date_time)),
+        };
+        hit.extra.insert("edge_location".to_string(), fields[2].to_string());
+        hit.bytes = match FromStr::from_str(fields[3]) {
+            Ok(bytes) => bytes,
+            Err(_) => return Err(format!("Invalid byte count \"{}\"", fields[3])),
+        };
+        hit.host = match normalize_host(fields[4]) {
+            Some(host) => host,
+            None => return Err(format!("Invalid host \"{}\"", fields[4])),
+        };
+        hit.method = fields[5].to_string();
+        hit.extra.insert("distribution".to_string(), fields[6].to_string());
+        set_path_and_query(&mut hit, fields[7]);
+        // 000 when the viewer closed the connection before we could respond
+        hit.status = match FromStr::from_str(fields[8]) {
+            Ok(0) | Err(_) => 999,
+            Ok(status) => status,
+        };
+        // Like other referers, without its query string
+        hit.referer = percent_decode(fields[9].splitn(2, '?').next().unwrap());
+        hit.agent = percent_decode(fields[10]);
+        if fields[11] != "-" {
+            hit.query = fields[11].to_string();
+        }
+        hit.extra.insert("result_type".to_string(), fields[13].to_string());
+        hit.extra.insert("host_header".to_string(), fields[15].to_string());
+        hit.duration = match f64::from_str(fields[18]) {
+            Ok(seconds) => Some((seconds * 1000000.0) as u64),
+            Err(_) => return Err(format!("Invalid time taken \"{}\"", fields[18])),
+        };
+        if let Some(&protocol) = fields.get(23) {
+            hit.protocol = protocol.to_string();
+        }
+        Ok(hit)
+    }
+
+    fn is_comment(&self, line: &str) -> bool {
+        line.starts_with('#')
+    }
+}
@@ -0,0 +1,101 @@
+use std::str::FromStr;
+use hit::{Hit, normalize_host};
+use parse::{LogParser, TimeFormat, parse_time, set_request};
+use token_parse::split_tokens;
+
+// The request types that start Application Load Balancer lines
+const ALB_TYPES: [&'static str; 6] = ["http", "https", "h2", "grpcs", "ws", "wss"];
+
+// Extra fields of Application Load Balancer lines that we keep, by how far after the user agent
+// they come
+const ALB_EXTRAS: [(usize, &'static str); 5] = [
+    (3, "target_group"), (4, "trace_id"), (5, "domain_name"), (9, "actions_executed"), (11, "error_reason"),
+];
+
+/// Returns the path of `url`, a full URL such as "http://www.example.com:80/index.html".
+fn url_path(url: &str) -> &str {
+    match url.find("://") {
+        Some(index) => {
+            let after_scheme = &url[index+3..];
+            match after_scheme.find('/') {
+                Some(index) => &after_scheme[index..],
+                None => "/",
+            }
+        },
+        None => url,
+    }
+}
+
+/// A parser for AWS load balancer access logs, of Application Load Balancers as well as Classic
+/// Load Balancers.
+///
+/// ```text
+/// 2015-05-13T23:39:43.945958Z my-loadbalancer 192.168.131.39:2817 10.0.0.1:80 0.000073 0.001048
+///     0.000057 200 200 0 29 "GET http://www.example.com:80/ HTTP/1.1" "curl/7.38.0" - -
+/// ```
+///
+/// Application Load Balancer lines start with the request type ("http", "h2"...) and have more
+/// fields after the ones above. The response time is the sum of the three processing times.
+///
+pub struct ElbParser;
+
+impl LogParser for ElbParser {
+    fn try_parse_line(&self, line: &str) -> Result<Hit, String> {
+        let tokens = match split_tokens(line) {
+            Ok(tokens) => tokens,
+            Err(e) => return Err(e),
+        };
+        let is_alb = tokens.first().map_or(false, |first| ALB_TYPES.contains(&&first[..]));
+        let tokens = if is_alb { &tokens[1..] } else { &tokens[..] };
+        if tokens.len() < 13 {
+            return Err("Missing fields".to_string());
+        }
+        let mut hit = Hit::new();
+        hit.time = match parse_time(&tokens[0], &TimeFormat::Iso8601) {
+            Some(tm) => tm,
+            None => return Err(format!("Invalid time \"{}\"", tokens[0])),
+        };
+        hit.extra.insert("elb".to_string(), tokens[1].to_string());
+        // "192.168.131.39:2817"
+        let client = tokens[2].rsplitn(2, ':').last().unwrap();
+        hit.host = match normalize_host(client) {
+            Some(host) => host,
+            None => return Err(format!("Invalid host \"{}\"", client)),
+        };
+        hit.extra.insert("target".to_string(), tokens[3].to_string());
+        // Processing times are -1 when the request didn't reach a target. We have no duration
+        // when none of them was measured.
+        let mut duration = None;
+        for value in tokens[4..7].iter() {
+            match f64::from_str(value) {
+                Ok(seconds) if seconds >= 0.0 => duration = Some(duration.unwrap_or(0.0) + seconds),
+                Ok(_) => {},
+                Err(_) => return Err(format!("Invalid processing time \"{}\"", value)),
+            }
+        }
+        hit.duration = duration.map(|seconds| (seconds * 1000000.0) as u64);
+        hit.status = match FromStr::from_str(&tokens[7]) {
+            Ok(status) => status,
+            Err(_) => 999,
+        };
+        hit.extra.insert("target_status".to_string(), tokens[8].to_string());
+        hit.bytes = match FromStr::from_str(&tokens[10]) {
+            Ok(bytes) => bytes,
+            Err(_) => return Err(format!("Invalid byte count \"{}\"", tokens[10])),
+        };
+        // TCP listeners log "- - - " as the request.
+        if let Err(e) = set_request(&mut hit, tokens[11].trim_end()) {
+            return Err(e);
+        }
+        hit.path = url_path(&hit.path).to_string();
+        hit.agent = tokens[12].to_string();
+        if is_alb {
+            for &(index, name) in ALB_EXTRAS.iter() {
+                if let Some(value) = tokens.get(12 + index) {
+                    hit.extra.insert(name.to_string(), value.to_string());
+                }
+            }
+        }
+        Ok(hit)
+    }
+}
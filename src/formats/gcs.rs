@@ -0,0 +1,96 @@
+use std::str::FromStr;
+use time::{at_utc, Timespec};
+use hit::{Hit, normalize_host};
+use parse::{LogParser, set_path_and_query};
+
+// Extra fields that we keep, by index
+const EXTRAS: [(usize, &'static str); 5] = [
+    (10, "host_header"), (13, "request_id"), (14, "operation"), (15, "bucket"), (16, "object"),
+];
+
+/// Splits `line`, a CSV line whose values are in quotes, into its values.
+fn split_csv(line: &str) -> Result<Vec<String>, String> {
+    let mut result = Vec::new();
+    let mut chars = line.trim_end_matches(|c| c == '\r' || c == '\n').chars().peekable();
+    loop {
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    // A doubled quote stands for a quote.
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        value.push('"');
+                        chars.next();
+                    },
+                    Some('"') => break,
+                    Some(c) => value.push(c),
+                    None => return Err("Unclosed quote".to_string()),
+                }
+            }
+        }
+        while let Some(&c) = chars.peek() {
+            if c == ',' {
+                break;
+            }
+            value.push(c);
+            chars.next();
+        }
+        result.push(value);
+        if chars.next().is_none() {
+            return Ok(result);
+        }
+    }
+}
+
+/// A parser for Google Cloud Storage usage logs, which are CSV files.
+///
+/// Fields are the ones of the header line, which we skip: `time_micros`, `c_ip`, `c_ip_type`,
+/// `c_ip_region`, `cs_method`, `cs_uri`, `sc_status`, `cs_bytes`, `sc_bytes`,
+/// `time_taken_micros`, `cs_host`, `cs_referer`, `cs_user_agent`, `s_request_id`,
+/// `cs_operation`, `cs_bucket` and `cs_object`.
+///
+pub struct GcsParser;
+
+impl LogParser for GcsParser {
+    fn try_parse_line(&self, line: &str) -> Result<Hit, String> {
+        let values = match split_csv(line) {
+            Ok(values) => values,
+            Err(e) => return Err(e),
+        };
+        if values.len() < 13 {
+            return Err("Missing fields".to_string());
+        }
+        let mut hit = Hit::new();
+        hit.time = match i64::from_str(&values[0]) {
+            Ok(micros) => at_utc(Timespec::new(micros / 1000000, 0)),
+            Err(_) => return Err(format!("Invalid time \"{}\"", values[0])),
+        };
+        hit.host = match normalize_host(&values[1]) {
+            Some(host) => host,
+            None => return Err(format!("Invalid host \"{}\"", values[1])),
+        };
+        hit.method = values[4].clone();
+        set_path_and_query(&mut hit, &values[5]);
+        hit.status = match FromStr::from_str(&values[6]) {
+            Ok(status) => status,
+            Err(_) => return Err(format!("Invalid status \"{}\"", values[6])),
+        };
+        hit.bytes = FromStr::from_str(&values[8]).unwrap_or(0);
+        hit.duration = FromStr::from_str(&values[9]).ok();
+        if !values[11].is_empty() {
+            hit.referer = values[11].splitn(2, '?').next().unwrap().to_string();
+        }
+        hit.agent = values[12].clone();
+        for &(index, name) in EXTRAS.iter() {
+            if let Some(value) = values.get(index) {
+                hit.extra.insert(name.to_string(), value.clone());
+            }
+        }
+        Ok(hit)
+    }
+
+    fn is_comment(&self, line: &str) -> bool {
+        line.starts_with("\"time_micros\"")
+    }
+}
@@ -0,0 +1,129 @@
+use std::str::FromStr;
+use hit::{Hit, normalize_host};
+use parse::{LogParser, TimeFormat, parse_time, set_request};
+
+// HAProxy's accept date, without its milliseconds. It's logged in local time.
+const ACCEPT_DATE_FORMAT: &'static str = "%d/%b/%Y:%H:%M:%S";
+
+/// Returns the first word of `line` and what follows it.
+fn next_word(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_start_matches(' ');
+    if line.is_empty() {
+        return None;
+    }
+    let end = line.find(' ').unwrap_or(line.len());
+    Some((&line[..end], &line[end..]))
+}
+
+/// Returns `value`, a number HAProxy logs, as a number.
+///
+/// With "option logasap", totals are logged before the end of the transfer and prefixed with
+/// a "+". Aborted timers are logged as -1, which we return as `None`.
+///
+fn parse_number(value: &str) -> Option<u64> {
+    FromStr::from_str(value.trim_start_matches('+')).ok()
+}
+
+/// A parser for HAProxy's HTTP log format, the one enabled by "option httplog".
+///
+/// ```text
+/// 10.0.1.2:33317 [06/Feb/2009:12:14:14.655] http-in static/srv1 10/0/30/69/109 200 2750 - - ----
+///     1/1/1/1/0 0/0 {1wt.eu} {} "GET /index.html HTTP/1.1"
+/// ```
+///
+/// Captured headers, in braces, are optional. Lines written by syslog, with a
+/// "haproxy[PID]: " header, are accepted too.
+///
+pub struct HaproxyParser;
+
+impl LogParser for HaproxyParser {
+    fn try_parse_line(&self, line: &str) -> Result<Hit, String> {
+        // Skip the syslog header, if any
+        let line = match (line.find("]: "), line.find('"')) {
+            (Some(index), Some(quote_index)) if index < quote_index => &line[index+3..],
+            _ => line,
+        };
+        let mut words: Vec<&str> = Vec::with_capacity(12);
+        let mut rest = line;
+        while words.len() < 12 {
+            match next_word(rest) {
+                Some((word, after)) => {
+                    words.push(word);
+                    rest = after;
+                },
+                None => return Err("Missing fields".to_string()),
+            }
+        }
+        let mut hit = Hit::new();
+        // "10.0.1.2:33317" or "::1:33317"
+        let client = words[0].rsplitn(2, ':').last().unwrap();
+        hit.host = match normalize_host(client) {
+            Some(host) => host,
+            None => return Err(format!("Invalid host \"{}\"", client)),
+        };
+        // "[06/Feb/2009:12:14:14.655]"
+        let accept_date = words[1];
+        if accept_date.len() < 2 || !accept_date.starts_with('[') || !accept_date.ends_with(']') {
+            return Err("Doesn't match the log format".to_string());
+        }
+        let accept_date = accept_date[1..accept_date.len()-1].splitn(2, '.').next().unwrap();
+        hit.time = match parse_time(accept_date, &TimeFormat::Strftime(ACCEPT_DATE_FORMAT.to_string())) {
+            Some(tm) => tm,
+            None => return Err(format!("Invalid date \"{}\"", accept_date)),
+        };
+        hit.extra.insert("frontend".to_string(), words[2].to_string());
+        let mut backend_server = words[3].splitn(2, '/');
+        hit.extra.insert("backend".to_string(), backend_server.next().unwrap().to_string());
+        match backend_server.next() {
+            Some(server) => { hit.extra.insert("server".to_string(), server.to_string()); },
+            None => return Err("Doesn't match the log format".to_string()),
+        }
+        // TR/Tw/Tc/Tr/Ta, or Tq/Tw/Tc/Tr/Tt for HAProxy before 1.7. TCP logs only have three.
+        let timers: Vec<&str> = words[4].split('/').collect();
+        if timers.len() != 5 {
+            return Err("Not an HTTP log line".to_string());
+        }
+        hit.extra.insert("timers".to_string(), words[4].to_string());
+        hit.duration = parse_number(timers[4]).and_then(|ms| ms.checked_mul(1000));
+        // -1 when the connection was aborted before a response was sent
+        hit.status = match FromStr::from_str(words[5]) {
+            Ok(status) if words[5].len() == 3 => status,
+            _ if words[5] == "-1" => 999,
+            _ => return Err(format!("Invalid status \"{}\"", words[5])),
+        };
+        hit.bytes = match parse_number(words[6]) {
+            Some(bytes) => bytes as u32,
+            None => return Err(format!("Invalid byte count \"{}\"", words[6])),
+        };
+        // words[7] and words[8] are captured cookies
+        hit.extra.insert("termination_state".to_string(), words[9].to_string());
+        hit.extra.insert("connections".to_string(), words[10].to_string());
+        hit.extra.insert("queues".to_string(), words[11].to_string());
+        // Captured request and response headers, then the request line in quotes
+        let mut rest = rest.trim_start_matches(' ');
+        let mut captures = Vec::new();
+        while rest.starts_with('{') {
+            match rest.find('}') {
+                Some(index) => {
+                    captures.push(&rest[1..index]);
+                    rest = rest[index+1..].trim_start_matches(' ');
+                },
+                None => return Err("Unclosed brace".to_string()),
+            }
+        }
+        for (name, capture) in ["request_headers", "response_headers"].iter().zip(captures.iter()) {
+            hit.extra.insert(name.to_string(), capture.to_string());
+        }
+        if !rest.starts_with('"') {
+            return Err("Missing request".to_string());
+        }
+        // Long requests are truncated, closing quote included.
+        let request = rest[1..].trim_end_matches(|c| c == '"' || c == ' ');
+        if request == "<BADREQ>" {
+            hit.path = request.to_string();
+        } else if let Err(e) = set_request(&mut hit, request) {
+            return Err(e);
+        }
+        Ok(hit)
+    }
+}
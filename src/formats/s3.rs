@@ -0,0 +1,64 @@
+use std::str::FromStr;
+use hit::{Hit, normalize_host};
+use parse::{LogParser, TimeFormat, parse_time, set_request};
+use token_parse::split_tokens;
+
+// Extra fields that we keep, by index
+const EXTRAS: [(usize, &'static str); 6] = [
+    (1, "bucket"), (4, "requester"), (5, "request_id"), (6, "operation"), (7, "key"), (10, "error_code"),
+];
+
+/// A parser for Amazon S3 server access logs.
+///
+/// ```text
+/// 79a59df900b949e5 awsexamplebucket1 [06/Feb/2019:00:00:38 +0000] 192.0.2.3 79a59df900b949e5
+///     3E57427F3EXAMPLE REST.GET.VERSIONING - "GET /awsexamplebucket1?versioning HTTP/1.1" 200 -
+///     113 - 7 - "-" "S3Console/0.4" - s9lzHYrFp76ZVxRcpX9+5cjAnEH2ROuNkd2BHfIa6UkFVdtjf5mKR3/eTPFvsiP=
+///     SigV4 ECDHE-RSA-AES128-GCM-SHA256 AuthHeader awsexamplebucket1.s3.us-west-1.amazonaws.com TLSV1.2
+/// ```
+///
+/// We need the fields up to the user agent. The response time is the total time.
+///
+pub struct S3Parser;
+
+impl LogParser for S3Parser {
+    fn try_parse_line(&self, line: &str) -> Result<Hit, String> {
+        let tokens = match split_tokens(line) {
+            Ok(tokens) => tokens,
+            Err(e) => return Err(e),
+        };
+        if tokens.len() < 17 {
+            return Err("Missing fields".to_string());
+        }
+        let mut hit = Hit::new();
+        hit.time = match parse_time(&tokens[2], &TimeFormat::Apache) {
+            Some(tm) => tm,
+            None => return Err(format!("Invalid time \"{}\"", tokens[2])),
+        };
+        hit.host = match normalize_host(&tokens[3]) {
+            Some(host) => host,
+            None => return Err(format!("Invalid host \"{}\"", tokens[3])),
+        };
+        for &(index, name) in EXTRAS.iter() {
+            hit.extra.insert(name.to_string(), tokens[index].to_string());
+        }
+        // Operations that S3 does on its own, such as lifecycle expirations, have no request.
+        if tokens[8] == "-" {
+            hit.path = tokens[7].to_string();
+        } else if let Err(e) = set_request(&mut hit, &tokens[8]) {
+            return Err(e);
+        }
+        hit.status = match FromStr::from_str(&tokens[9]) {
+            Ok(status) => status,
+            Err(_) => 999,
+        };
+        hit.bytes = FromStr::from_str(&tokens[11]).unwrap_or(0);
+        hit.duration = u64::from_str(&tokens[13]).ok().and_then(|ms| ms.checked_mul(1000));
+        hit.referer = tokens[15].splitn(2, '?').next().unwrap().to_string();
+        hit.agent = tokens[16].to_string();
+        if let Some(host_header) = tokens.get(22) {
+            hit.extra.insert("host_header".to_string(), host_header.to_string());
+        }
+        Ok(hit)
+    }
+}
@@ -0,0 +1,71 @@
+use std::str::FromStr;
+use hit::{Hit, normalize_host};
+use parse::{LogParser, TimeFormat, parse_time, set_path_and_query};
+
+/// A parser for Squid's native access log format, "squid" in squid.conf.
+///
+/// ```text
+/// 1286536309.450     30 192.168.0.68 TCP_MISS/200 1234 GET http://www.example.com/ - HIER_DIRECT/93.184.216.34 text/html
+/// ```
+///
+/// That's the time, the elapsed time in milliseconds, the client, the Squid result code and the
+/// status, the size of the reply, the method, the URL, the user, the hierarchy code and the peer,
+/// and the content type. Fields are aligned with spaces.
+///
+pub struct SquidParser;
+
+impl LogParser for SquidParser {
+    fn try_parse_line(&self, line: &str) -> Result<Hit, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() < 7 {
+            return Err("Missing fields".to_string());
+        }
+        let mut hit = Hit::new();
+        hit.time = match parse_time(words[0], &TimeFormat::Epoch) {
+            Some(tm) => tm,
+            None => return Err(format!("Invalid time \"{}\"", words[0])),
+        };
+        hit.duration = match u64::from_str(words[1]).ok().and_then(|ms| ms.checked_mul(1000)) {
+            Some(micros) => Some(micros),
+            None => return Err(format!("Invalid elapsed time \"{}\"", words[1])),
+        };
+        hit.host = match normalize_host(words[2]) {
+            Some(host) => host,
+            None => return Err(format!("Invalid host \"{}\"", words[2])),
+        };
+        // "TCP_MISS/200". The status is 000 when there was no reply at all.
+        let mut code_status = words[3].splitn(2, '/');
+        hit.extra.insert("result_code".to_string(), code_status.next().unwrap().to_string());
+        hit.status = match code_status.next().map(u32::from_str) {
+            Some(Ok(0)) => 999,
+            Some(Ok(status)) => status,
+            _ => return Err(format!("Invalid result code \"{}\"", words[3])),
+        };
+        hit.bytes = match u32::from_str(words[4]) {
+            Ok(bytes) => bytes,
+            Err(_) => return Err(format!("Invalid byte count \"{}\"", words[4])),
+        };
+        hit.method = words[5].to_string();
+        // A full URL for proxied requests, "host:port" for CONNECT
+        set_path_and_query(&mut hit, words[6]);
+        if let Some(&user) = words.get(7) {
+            hit.extra.insert("user".to_string(), user.to_string());
+        }
+        if let Some(hierarchy) = words.get(8) {
+            // "HIER_DIRECT/93.184.216.34"
+            let mut code_peer = hierarchy.splitn(2, '/');
+            hit.extra.insert("hierarchy_code".to_string(), code_peer.next().unwrap().to_string());
+            if let Some(peer) = code_peer.next() {
+                hit.extra.insert("peer".to_string(), peer.to_string());
+            }
+        }
+        if let Some(&content_type) = words.get(9) {
+            hit.extra.insert("content_type".to_string(), content_type.to_string());
+        }
+        Ok(hit)
+    }
+
+    fn parses_fully(&self, line: &str) -> bool {
+        line.split_whitespace().count() >= 10 && self.try_parse_line(line).is_ok()
+    }
+}
@@ -0,0 +1,47 @@
+use std::str::FromStr;
+use hit::Hit;
+use parse::{LogParser, Parser};
+
+// Traefik's "common" format, as an nginx log format. The extras are named after Traefik's fields.
+const TRAEFIK_COMMON_FORMAT: &'static str = r#"$remote_addr - $remote_user [$time_local] "$request" $status $body_bytes_sent "$http_referer" "$http_user_agent" $request_count "$router_name" "$service_url" $duration"#;
+
+/// A parser for Traefik's "common" access log format, which is "combined" followed by the
+/// request count, the router, the service URL and the duration.
+///
+/// ```text
+/// 10.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET / HTTP/1.1" 200 12 "-" "curl/7" 42 "web@docker"
+///     "http://172.17.0.3:80" 3ms
+/// ```
+///
+pub struct TraefikParser {
+    parser: Parser,
+}
+
+impl TraefikParser {
+    pub fn new() -> Result<TraefikParser, String> {
+        Parser::from_nginx_format(TRAEFIK_COMMON_FORMAT).map(|parser| TraefikParser { parser: parser })
+    }
+
+    /// Turns the "3ms" that our format leaves in `hit.extra` into `hit.duration`.
+    fn set_duration(&self, mut hit: Hit) -> Result<Hit, String> {
+        if let Some(duration) = hit.extra.remove("duration") {
+            let ms = if duration.ends_with("ms") { &duration[..duration.len()-2] } else { "" };
+            hit.duration = match u64::from_str(ms).ok().and_then(|ms| ms.checked_mul(1000)) {
+                Some(micros) => Some(micros),
+                None => return Err(format!("Invalid duration \"{}\"", duration)),
+            };
+        }
+        Ok(hit)
+    }
+}
+
+impl LogParser for TraefikParser {
+    fn try_parse_line(&self, line: &str) -> Result<Hit, String> {
+        self.parser.try_parse_line(line).and_then(|hit| self.set_duration(hit))
+    }
+
+    fn parses_fully(&self, line: &str) -> bool {
+        // A "combined" line followed by anything has all our fields, so the duration must be right too.
+        self.parser.parses_fully(line) && self.try_parse_line(line).is_ok()
+    }
+}
@@ -29,11 +29,20 @@ impl HelpPanel {
             "h - Host mode",
             "p - Path mode",
             "r - Referer mode",
+            "s - Site mode",
+            "l - Slow mode (paths by latency)",
+            "d - Visit details (Host mode)",
+            "e - Rejected lines",
+            "a - Toggle query strings in Path mode",
+            "z - Toggle UTC/local time",
+            "space - Pause/resume replay",
+            "n - Step replay by a minute",
+            "[/] - Seek replay by 10 minutes",
             "↑/↓ - Selection",
             "q - Quit/Close panel",
         ];
-        let width = 25;
-        let w = newwin(7, width, 1, self.scrx - width);
+        let width = 40;
+        let w = newwin((lines.len() + 2) as i32, width, 1, self.scrx - width);
         for (index, text) in lines.iter().enumerate() {
             mvwprintw(w, (index+1) as i32, 1, text);
         }
@@ -1,19 +1,72 @@
-use time::strftime;
+use std::collections::hash_map::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use time::{strftime, now_utc, Tm};
 
 const PAGE_EXTS: [&'static str; 3] = ["html", "htm", "php"];
 
+/// Timezone in which we display times. Internally, hit times are always in UTC.
+#[derive(PartialEq, Copy, Clone)]
+pub enum TimeDisplay {
+    Local,
+    UTC,
+}
+
+pub fn fmt_hour_minute(tm: &Tm, display: TimeDisplay) -> String {
+    match display {
+        TimeDisplay::Local => strftime("%H:%M", &tm.to_local()).unwrap(),
+        TimeDisplay::UTC => strftime("%H:%M", &tm.to_utc()).unwrap(),
+    }
+}
+
+pub fn fmt_date_time(tm: &Tm, display: TimeDisplay) -> String {
+    match display {
+        TimeDisplay::Local => strftime("%Y-%m-%d %H:%M:%S", &tm.to_local()).unwrap(),
+        TimeDisplay::UTC => strftime("%Y-%m-%d %H:%M:%S", &tm.to_utc()).unwrap(),
+    }
+}
+
 #[derive(Clone)]
 pub struct Hit {
     pub host: String,
     pub time: ::time::Tm,
     pub status: u32,
     pub bytes: u32,
+    pub method: String,
     pub path: String,
+    // Raw query string, without the "?"
+    pub query: String,
+    pub protocol: String,
+    // Time taken to serve the request, in microseconds, if the log format has it
+    pub duration: Option<u64>,
     pub referer: String,
     pub agent: String,
+    // Fields from the log format that don't have a place above, by name
+    pub extra: HashMap<String, String>,
+    // Name of the site whose log the hit comes from. Empty when we only follow one site.
+    pub site: String,
 }
 
 impl Hit {
+    /// Returns a hit with nothing known about it yet, for parsers to fill.
+    pub fn new() -> Hit {
+        Hit {
+            host: String::new(),
+            time: now_utc(),
+            status: 999,
+            bytes: 0,
+            method: String::new(),
+            path: String::new(),
+            query: String::new(),
+            protocol: String::new(),
+            duration: None,
+            referer: "-".to_string(),
+            agent: String::new(),
+            extra: HashMap::new(),
+            site: String::new(),
+        }
+    }
+
     pub fn is_4xx(&self) -> bool {
         self.status >= 400 && self.status < 500
     }
@@ -22,8 +75,21 @@ impl Hit {
         self.status >= 400 && self.status < 500
     }
 
-    pub fn fmt_time(&self) -> String {
-        strftime("%H:%M", &self.time).unwrap()
+    pub fn fmt_time(&self, display: TimeDisplay) -> String {
+        fmt_hour_minute(&self.time, display)
+    }
+
+    pub fn path_with_query(&self) -> String {
+        if self.query.is_empty() {
+            self.path.clone()
+        } else {
+            format!("{}?{}", self.path, self.query)
+        }
+    }
+
+    /// Returns our path, prefixed with our site name.
+    pub fn site_path(&self) -> String {
+        format!("{}{}", self.site, self.path)
     }
 
     pub fn is_resource(&self) -> bool {
@@ -45,3 +111,31 @@ pub fn is_path_resource(path: &str) -> bool {
     !PAGE_EXTS.contains(&ext)
 }
 
+
+/// Returns the canonical form of `host`, or `None` if it doesn't look like a host at all.
+///
+/// The same client can be logged under different spellings (full or compressed IPv6, IPv4-mapped
+/// IPv6, hostname case), but we want it to always end up in the same visit. IPv6 addresses are
+/// compressed, IPv4-mapped addresses are turned back into IPv4 and hostnames are lowercased.
+///
+pub fn normalize_host(host: &str) -> Option<String> {
+    // "[::1]" --> "::1"
+    let host = host.trim_matches(|c| c == '[' || c == ']');
+    if let Ok(addr) = Ipv4Addr::from_str(host) {
+        return Some(addr.to_string());
+    }
+    if let Ok(addr) = Ipv6Addr::from_str(host) {
+        let seg = addr.segments();
+        if seg[..5].iter().all(|s| *s == 0) && seg[5] == 0xffff {
+            let v4 = Ipv4Addr::new(
+                (seg[6] >> 8) as u8, (seg[6] & 0xff) as u8, (seg[7] >> 8) as u8, (seg[7] & 0xff) as u8
+            );
+            return Some(v4.to_string());
+        }
+        return Some(addr.to_string());
+    }
+    let is_hostname = !host.is_empty() && host.chars().all(
+        |c| c.is_alphanumeric() || c == '.' || c == '-' || c == '_'
+    );
+    if is_hostname { Some(host.to_lowercase()) } else { None }
+}
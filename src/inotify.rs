@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::ffi::{CString, OsString};
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::mem;
+use std::sync::Mutex;
+use std::sync::mpsc::{sync_channel, SyncSender, Receiver};
+use std::thread;
+use std::time::Duration;
+use libc::{c_int, c_char, c_short, c_ulong, c_void, size_t, read, close};
+
+const IN_MODIFY: u32 = 0x2;
+const IN_MOVED_FROM: u32 = 0x40;
+const IN_MOVED_TO: u32 = 0x80;
+const IN_CREATE: u32 = 0x100;
+const IN_DELETE: u32 = 0x200;
+const IN_CLOEXEC: c_int = 0o2000000;
+const POLLIN: c_short = 0x1;
+
+// What we watch directories for. Each watcher then picks the events it's interested in.
+const DIR_MASK: u32 = IN_MODIFY | IN_MOVED_FROM | IN_MOVED_TO | IN_CREATE | IN_DELETE;
+
+// How long, in milliseconds, a directory's thread waits for events before checking whether it's
+// still being watched
+const DIR_POLL_TIMEOUT: c_int = 1000;
+
+#[repr(C)]
+struct InotifyEvent {
+    wd: c_int,
+    mask: u32,
+    cookie: u32,
+    len: u32,
+    // Followed by `len` bytes of NUL-padded file name
+}
+
+#[repr(C)]
+struct PollFd {
+    fd: c_int,
+    events: c_short,
+    revents: c_short,
+}
+
+extern "C" {
+    fn inotify_init1(flags: c_int) -> c_int;
+    fn inotify_add_watch(fd: c_int, pathname: *const c_char, mask: u32) -> c_int;
+    fn poll(fds: *mut PollFd, nfds: c_ulong, timeout: c_int) -> c_int;
+}
+
+/// A `FileWatcher` as its directory's thread knows it.
+struct Subscriber {
+    id: usize,
+    // `None` when we watch the whole directory
+    filename: Option<OsString>,
+    mask: u32,
+    tx: SyncSender<()>,
+}
+
+struct Registry {
+    next_id: usize,
+    // Who's interested in what happens in each directory we watch
+    dirs: HashMap<PathBuf, Vec<Subscriber>>,
+}
+
+// Directories being watched. Followers of files in the same directory share its inotify instance,
+// so that there's one instance per directory rather than per file, and so that followers aren't
+// woken up by events about each other's files.
+static REGISTRY: Mutex<Option<Registry>> = Mutex::new(None);
+
+/// Waits for events on `fd`, for at most `timeout_ms` milliseconds, and returns them as (mask,
+/// file name).
+fn read_events(fd: c_int, timeout_ms: c_int) -> Vec<(u32, Vec<u8>)> {
+    let mut result = Vec::new();
+    let mut pollfd = PollFd { fd: fd, events: POLLIN, revents: 0 };
+    if unsafe { poll(&mut pollfd, 1, timeout_ms) } <= 0 {
+        return result;
+    }
+    // u32s rather than u8s so that events are properly aligned
+    let mut buf = [0u32; 1024];
+    let bufsize = buf.len() * mem::size_of::<u32>();
+    let read_size = unsafe { read(fd, buf.as_mut_ptr() as *mut c_void, bufsize as size_t) };
+    if read_size <= 0 {
+        return result;
+    }
+    let bytes = unsafe {
+        ::std::slice::from_raw_parts(buf.as_ptr() as *const u8, read_size as usize)
+    };
+    let header_size = mem::size_of::<InotifyEvent>();
+    let mut offset = 0;
+    while offset + header_size <= bytes.len() {
+        let event = unsafe { &*(bytes[offset..].as_ptr() as *const InotifyEvent) };
+        let name_start = offset + header_size;
+        let name_end = name_start + event.len as usize;
+        let name: Vec<u8> = bytes[name_start..name_end].iter()
+            .take_while(|b| **b != 0)
+            .cloned()
+            .collect();
+        result.push((event.mask, name));
+        offset = name_end;
+    }
+    result
+}
+
+/// Reads the events of `dir`'s inotify instance, `fd`, and wakes up the watchers they concern.
+///
+/// Stops, and closes `fd`, when nobody watches `dir` anymore.
+///
+fn watch_dir(dir: PathBuf, fd: c_int) {
+    loop {
+        let events = read_events(fd, DIR_POLL_TIMEOUT);
+        let mut registry = REGISTRY.lock().unwrap();
+        let registry = registry.as_mut().unwrap();
+        let is_watched = {
+            let subscribers = registry.dirs.get_mut(&dir).unwrap();
+            for &(mask, ref name) in events.iter() {
+                for subscriber in subscribers.iter() {
+                    let concerned = subscriber.mask & mask != 0 && match subscriber.filename {
+                        Some(ref filename) => &name[..] == filename.as_bytes(),
+                        None => true,
+                    };
+                    if concerned {
+                        // If a wakeup is already pending, that's enough.
+                        let _ = subscriber.tx.try_send(());
+                    }
+                }
+            }
+            !subscribers.is_empty()
+        };
+        if !is_watched {
+            registry.dirs.remove(&dir);
+            unsafe { close(fd) };
+            return;
+        }
+    }
+}
+
+/// Tells us when something happens to a file, or to a directory's listing, through inotify.
+///
+/// We watch the file's directory rather than the file itself because we're also interested in the
+/// file being replaced by a new one, which happens on log rotation.
+///
+pub struct FileWatcher {
+    dir: PathBuf,
+    id: usize,
+    events: Receiver<()>,
+}
+
+impl FileWatcher {
+    /// Returns `None` if inotify can't be used on `path`.
+    pub fn new(path: &Path) -> Option<FileWatcher> {
+        let filename = match path.file_name() {
+            Some(filename) => filename.to_os_string(),
+            None => return None,
+        };
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        FileWatcher::watch(dir, DIR_MASK, Some(filename))
+    }
+
+    /// Watches files being added to or removed from `dir`, but not their contents.
+    ///
+    /// Returns `None` if inotify can't be used on `dir`.
+    ///
+    pub fn for_dir(dir: &Path) -> Option<FileWatcher> {
+        let mask = IN_MOVED_FROM | IN_MOVED_TO | IN_CREATE | IN_DELETE;
+        FileWatcher::watch(dir, mask, None)
+    }
+
+    fn watch(dir: &Path, mask: u32, filename: Option<OsString>) -> Option<FileWatcher> {
+        // So that "logs" and "./logs" share their instance
+        let dir = fs::canonicalize(dir).unwrap_or(dir.to_path_buf());
+        let mut registry = REGISTRY.lock().unwrap();
+        let registry = registry.get_or_insert_with(|| Registry { next_id: 0, dirs: HashMap::new() });
+        if !registry.dirs.contains_key(&dir) {
+            let c_dir = match CString::new(dir.as_os_str().as_bytes()) {
+                Ok(c_dir) => c_dir,
+                Err(_) => return None,
+            };
+            let fd = unsafe { inotify_init1(IN_CLOEXEC) };
+            if fd < 0 {
+                return None;
+            }
+            if unsafe { inotify_add_watch(fd, c_dir.as_ptr(), DIR_MASK) } < 0 {
+                unsafe { close(fd) };
+                return None;
+            }
+            registry.dirs.insert(dir.clone(), Vec::new());
+            let dir = dir.clone();
+            thread::spawn(move || watch_dir(dir, fd));
+        }
+        let (tx, rx) = sync_channel(1);
+        let id = registry.next_id;
+        registry.next_id += 1;
+        registry.dirs.get_mut(&dir).unwrap().push(Subscriber {
+            id: id,
+            filename: filename,
+            mask: mask,
+            tx: tx,
+        });
+        Some(FileWatcher {
+            dir: dir,
+            id: id,
+            events: rx,
+        })
+    }
+
+    /// Waits for something to happen to what we watch, for at most `timeout_ms` milliseconds.
+    ///
+    /// Returns whether something happened.
+    ///
+    pub fn wait(&mut self, timeout_ms: i32) -> bool {
+        if timeout_ms <= 0 {
+            return false;
+        }
+        self.events.recv_timeout(Duration::from_millis(timeout_ms as u64)).is_ok()
+    }
+}
+
+impl Drop for FileWatcher {
+    fn drop(&mut self) {
+        // Our directory's thread stops once it has no watchers left.
+        let mut registry = REGISTRY.lock().unwrap();
+        if let Some(subscribers) = registry.as_mut().and_then(|registry| registry.dirs.get_mut(&self.dir)) {
+            let id = self.id;
+            subscribers.retain(|subscriber| subscriber.id != id);
+        }
+    }
+}
@@ -0,0 +1,421 @@
+use std::io::prelude::*;
+use std::io;
+use std::fs;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender, Receiver, TrySendError};
+use std::thread;
+use std::time::Duration;
+use time::precise_time_s;
+use tail::{FileTailer, TailEvent, Backfill};
+use inotify::FileWatcher;
+use line_buffer::LineBuffer;
+
+// When we can't get notified of changes, we poll at this interval, in milliseconds.
+const POLL_INTERVAL: u32 = 1000;
+
+// How much we read from STDIN at once, at most
+const STDIN_READ_SIZE: usize = 65536;
+
+// How many messages our input threads can send before the main loop takes them. Data messages
+// hold up to a read's worth of lines each.
+const INPUT_QUEUE_SIZE: usize = 64;
+
+// Extensions of compressed logs, which we never follow as we can't read them
+const COMPRESSED_EXTS: [&'static str; 5] = ["gz", "bz2", "xz", "zst", "zip"];
+
+// Files followed by a directory follower. Each file follower removes its file when it stops.
+type FollowedFiles = Arc<Mutex<HashSet<PathBuf>>>;
+
+/// What our input threads send to the main loop.
+pub enum InputMsg {
+    // Name of the site the data comes from, the data, and when we read it (`precise_time_s()`)
+    Data(String, String, f64),
+    // Something to tell the user about, and whether it stays true until the next note
+    Note(String, bool),
+    // A line we couldn't make sense of before even parsing it: why, and (part of) the line
+    Rejected(String, String),
+    // How many lines we read had invalid bytes, which we replaced
+    InvalidBytes(usize),
+}
+
+impl InputMsg {
+    /// Returns a message for `data`, which we just read.
+    pub fn data(site: &str, data: String) -> InputMsg {
+        InputMsg::Data(site.to_string(), data, precise_time_s())
+    }
+}
+
+/// Adds messages for the complete lines that `data` gives `line_buffer` to `msgs`.
+pub fn push_line_msgs(site: &str, line_buffer: &mut LineBuffer, data: &[u8], msgs: &mut Vec<InputMsg>) {
+    let (lines, replaced_count, overlong) = line_buffer.push(data);
+    if !lines.is_empty() {
+        msgs.push(InputMsg::data(site, lines));
+    }
+    if replaced_count > 0 {
+        msgs.push(InputMsg::InvalidBytes(replaced_count));
+    }
+    for line in overlong.into_iter() {
+        msgs.push(InputMsg::Rejected("Line too long".to_string(), line));
+    }
+}
+
+/// Adds messages for the line `line_buffer` was in the middle of, if any, to `msgs`.
+///
+/// That's for when the source ended: its last line might not have a newline.
+///
+pub fn flush_line_msgs(site: &str, line_buffer: &mut LineBuffer, msgs: &mut Vec<InputMsg>) {
+    let (line, replaced_count) = line_buffer.flush();
+    if !line.is_empty() {
+        msgs.push(InputMsg::data(site, line));
+    }
+    if replaced_count > 0 {
+        msgs.push(InputMsg::InvalidBytes(replaced_count));
+    }
+}
+
+/// What our input threads use to send messages to the main loop.
+///
+/// There's only so much room in the channel. When the main loop is behind, sources that can
+/// wait (files, pipes, commands) wait, which makes their writer wait as well. Sources that can't
+/// (syslog datagrams) drop their data instead, and we count the lines we dropped.
+///
+#[derive(Clone)]
+pub struct InputSender {
+    tx: SyncSender<InputMsg>,
+    dropped_lines: Arc<AtomicUsize>,
+}
+
+/// Returns both ends of a new input channel, and the count of lines dropped on the way.
+pub fn input_channel() -> (InputSender, Receiver<InputMsg>, Arc<AtomicUsize>) {
+    let (tx, rx) = sync_channel(INPUT_QUEUE_SIZE);
+    let dropped_lines = Arc::new(AtomicUsize::new(0));
+    let sender = InputSender {
+        tx: tx,
+        dropped_lines: dropped_lines.clone(),
+    };
+    (sender, rx, dropped_lines)
+}
+
+impl InputSender {
+    /// Sends `msg`, waiting for room if needed. Returns false if the main loop is gone.
+    pub fn send(&self, msg: InputMsg) -> bool {
+        self.tx.send(msg).is_ok()
+    }
+
+    pub fn send_all(&self, msgs: Vec<InputMsg>) -> bool {
+        for msg in msgs.into_iter() {
+            if !self.send(msg) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Sends `msg` if there's room for it right now, or drops it. Returns false if the main
+    /// loop is gone.
+    pub fn offer(&self, msg: InputMsg) -> bool {
+        match self.tx.try_send(msg) {
+            Ok(()) => true,
+            Err(TrySendError::Full(InputMsg::Data(_, data, _))) => {
+                let line_count = data.split('\n').filter(|l| !l.trim().is_empty()).count();
+                self.dropped_lines.fetch_add(line_count, Ordering::Relaxed);
+                true
+            },
+            Err(TrySendError::Full(_)) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+// How much data we gather, in bytes, before sending it
+const BATCH_SIZE: usize = 65536;
+
+// How long, in seconds, we hold data before sending it, at most
+pub const BATCH_DELAY: f64 = 0.1;
+
+/// Gathers the data of sources that come in many small pieces, such as syslog messages, so that we
+/// send it in a few big messages.
+pub struct Batch {
+    // Data by site, in the order sites came in
+    data: Vec<(String, String)>,
+    size: usize,
+    // How many of our lines had invalid bytes
+    replaced_count: usize,
+    // When the oldest data we hold was read
+    started_at: f64,
+}
+
+impl Batch {
+    pub fn new() -> Batch {
+        Batch {
+            data: Vec::new(),
+            size: 0,
+            replaced_count: 0,
+            started_at: 0.0,
+        }
+    }
+
+    pub fn push(&mut self, site: &str, data: &str, replaced_count: usize) {
+        self.replaced_count += replaced_count;
+        if self.data.is_empty() {
+            self.started_at = precise_time_s();
+        }
+        match self.data.iter().position(|d| d.0 == site) {
+            Some(index) => self.data[index].1.push_str(data),
+            None => self.data.push((site.to_string(), data.to_string())),
+        }
+        self.size += data.len();
+    }
+
+    /// Returns whether we've held our data long enough, or have enough of it, to send it.
+    pub fn is_due(&self) -> bool {
+        !self.data.is_empty() && (self.size >= BATCH_SIZE || precise_time_s() - self.started_at >= BATCH_DELAY)
+    }
+
+    /// Returns messages for the data we hold, and empties the batch.
+    pub fn take(&mut self) -> Vec<InputMsg> {
+        let started_at = self.started_at;
+        self.size = 0;
+        let mut msgs: Vec<InputMsg> = self.data.drain(..).map(|(site, data)| InputMsg::Data(site, data, started_at)).collect();
+        if self.replaced_count > 0 {
+            msgs.push(InputMsg::InvalidBytes(self.replaced_count));
+            self.replaced_count = 0;
+        }
+        msgs
+    }
+}
+
+/// Returns the site name we give to a log file when the user doesn't name it.
+///
+/// That's the file name without its extension and without the "access" part it usually has.
+/// For example, "example.com.access.log" and "example.com-access.log" give "example.com".
+///
+pub fn site_name_from_path(path: &Path) -> String {
+    let filename = match path.file_name() {
+        Some(filename) => filename.to_string_lossy().into_owned(),
+        None => return path.to_string_lossy().into_owned(),
+    };
+    let mut name = &filename[..];
+    if name.ends_with(".log") {
+        name = &name[..name.len() - 4];
+    }
+    for suffix in [".access", "-access", "_access"].iter() {
+        if name.ends_with(suffix) {
+            name = &name[..name.len() - suffix.len()];
+        }
+    }
+    if name.is_empty() { filename.clone() } else { name.to_string() }
+}
+
+/// Reads STDIN in a thread and sends its lines through `tx`, under the `site` name.
+///
+/// When STDIN is closed, we say so with a lasting note.
+///
+/// This thread is likely to be blocked in a read when the program ends, which is fine: it doesn't
+/// hold anything that needs cleaning up, and nobody waits for it.
+///
+pub fn spawn_stdin_reader(site: String, tx: InputSender) {
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut stdin = stdin.lock();
+        let mut line_buffer = LineBuffer::new();
+        let mut buf = vec![0u8; STDIN_READ_SIZE];
+        loop {
+            let read_size = match stdin.read(&mut buf) {
+                Ok(0) => break,
+                Ok(read_size) => read_size,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            let mut msgs = Vec::new();
+            push_line_msgs(&site, &mut line_buffer, &buf[..read_size], &mut msgs);
+            if !tx.send_all(msgs) {
+                return;
+            }
+        }
+        let mut msgs = Vec::new();
+        flush_line_msgs(&site, &mut line_buffer, &mut msgs);
+        let msg = if site.is_empty() { "Input ended.".to_string() } else { format!("Input ended for {}.", site) };
+        msgs.push(InputMsg::Note(msg, true));
+        tx.send_all(msgs);
+    });
+}
+
+/// Follows the file at `path` in a thread and sends what's appended to it through `tx`, under
+/// the `site` name. We start reading where `backfill` tells us to.
+///
+/// We use inotify to know when there's something new to read. If it's not available, we poll.
+///
+pub fn spawn_file_follower(path: &Path, site: String, backfill: Backfill, tx: InputSender) {
+    follow_file(path, site, backfill, tx, None);
+}
+
+/// Follows `path` in a thread.
+///
+/// When `followed` is given, we stop following our file when it's removed, and we remove it
+/// from `followed`. Otherwise, we wait for it to come back.
+///
+fn follow_file(
+    path: &Path, site: String, backfill: Backfill, tx: InputSender, followed: Option<FollowedFiles>
+) {
+    let path: PathBuf = path.to_path_buf();
+    thread::spawn(move || {
+        let mut tailer = FileTailer::new(&path, backfill);
+        let mut watcher = FileWatcher::new(&path);
+        let mut line_buffer = LineBuffer::new();
+        loop {
+            let mut msgs = Vec::new();
+            let mut stop = false;
+            match tailer.read() {
+                Ok((contents, event)) => {
+                    // The tailer ends the last line of a file when it stops reading it, so we
+                    // never glue lines from two files together.
+                    push_line_msgs(&site, &mut line_buffer, &contents, &mut msgs);
+                    match (event, followed.as_ref()) {
+                        (Some(TailEvent::Deleted), Some(followed)) => {
+                            followed.lock().unwrap().remove(&path);
+                            let msg = format!("{} was removed, not following it anymore.", path.display());
+                            msgs.push(InputMsg::Note(msg, false));
+                            stop = true;
+                        },
+                        (Some(event), _) => {
+                            // We keep telling about a missing file for as long as it's missing.
+                            let lasting = match event { TailEvent::Deleted => true, _ => false };
+                            msgs.push(InputMsg::Note(event.describe(&path), lasting));
+                        },
+                        (None, _) => (),
+                    }
+                },
+                Err(e) => {
+                    let msg = format!("Had troube reading {}! Error: {}", path.display(), e);
+                    msgs.push(InputMsg::Note(msg, false));
+                },
+            };
+            if !tx.send_all(msgs) || stop {
+                return;
+            }
+            if tailer.has_more() {
+                continue;
+            }
+            match watcher {
+                // We also wake up once in a while in case we missed something.
+                Some(ref mut watcher) => { watcher.wait(POLL_INTERVAL as i32); },
+                None => thread::sleep(Duration::from_millis(POLL_INTERVAL as u64)),
+            }
+        }
+    });
+}
+
+/// Returns whether `name` matches `pattern`, where `*` matches any sequence of characters and
+/// `?` matches any single character.
+pub fn matches_pattern(name: &str, pattern: &str) -> bool {
+    let name: Vec<char> = name.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    // Position in `pattern` right after the last `*`, and position in `name` it matched up to
+    let mut star: Option<(usize, usize)> = None;
+    let (mut ni, mut pi) = (0, 0);
+    while ni < name.len() {
+        if pi < pattern.len() && (pattern[pi] == '?' || pattern[pi] == name[ni]) {
+            ni += 1;
+            pi += 1;
+        }
+        else if pi < pattern.len() && pattern[pi] == '*' {
+            pi += 1;
+            star = Some((pi, ni));
+        }
+        else {
+            match star {
+                // Let the last `*` match one more character and try again from there.
+                Some((star_pi, star_ni)) => {
+                    pi = star_pi;
+                    ni = star_ni + 1;
+                    star = Some((star_pi, ni));
+                },
+                None => return false,
+            }
+        }
+    }
+    pattern[pi..].iter().all(|c| *c == '*')
+}
+
+/// Returns the extension of `name`, the part after its last ".", if any.
+fn extension(name: &str) -> &str {
+    match name.rfind('.') {
+        Some(index) => &name[index+1..],
+        None => "",
+    }
+}
+
+/// Returns whether `name` looks like a compressed log, such as "access.log.2.gz".
+fn is_compressed_log(name: &str) -> bool {
+    COMPRESSED_EXTS.contains(&extension(name))
+}
+
+/// Returns whether `name` looks like an old, rotated log rather than a live one.
+///
+/// That is, whether it's compressed ("access.log.2.gz") or numbered ("access.log.1").
+///
+fn is_rotated_log(name: &str) -> bool {
+    let ext = extension(name);
+    is_compressed_log(name) || (!ext.is_empty() && ext.chars().all(|c| c.is_digit(10)))
+}
+
+/// Returns the files in `dir` we should follow, sorted.
+///
+/// With a `pattern`, those are the files matching it, except compressed ones. Otherwise, those
+/// are all files that don't look like rotated logs.
+///
+fn list_log_files(dir: &Path, pattern: Option<&str>) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut result: Vec<PathBuf> = entries.filter_map(|entry| entry.ok()).filter(|entry| {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let wanted = match pattern {
+            Some(pattern) => matches_pattern(&name, pattern) && !is_compressed_log(&name),
+            None => !name.starts_with('.') && !is_rotated_log(&name),
+        };
+        wanted && fs::metadata(entry.path()).map(|m| m.is_file()).unwrap_or(false)
+    }).map(|entry| entry.path()).collect();
+    result.sort();
+    result
+}
+
+/// Follows, in a thread, all log files in `dir` (see `list_log_files()`), as they come and go.
+///
+/// Each file is a site named after it. Files that are there from the start are read from where
+/// `backfill` tells us to. Files that appear later are read from their start.
+///
+pub fn spawn_dir_follower(dir: &Path, pattern: Option<String>, backfill: Backfill, tx: InputSender) {
+    let dir: PathBuf = dir.to_path_buf();
+    thread::spawn(move || {
+        let followed: FollowedFiles = Arc::new(Mutex::new(HashSet::new()));
+        let mut watcher = FileWatcher::for_dir(&dir);
+        let mut first_scan = true;
+        loop {
+            for path in list_log_files(&dir, pattern.as_ref().map(|p| &p[..])).into_iter() {
+                let is_new = followed.lock().unwrap().insert(path.clone());
+                if !is_new {
+                    continue;
+                }
+                if !first_scan {
+                    let msg = format!("Now following {}.", path.display());
+                    if !tx.send(InputMsg::Note(msg, false)) {
+                        return;
+                    }
+                }
+                let backfill = if first_scan { backfill.clone() } else { Backfill::Start };
+                follow_file(&path, site_name_from_path(&path), backfill, tx.clone(), Some(followed.clone()));
+            }
+            first_scan = false;
+            match watcher {
+                Some(ref mut watcher) => { watcher.wait(POLL_INTERVAL as i32); },
+                None => thread::sleep(Duration::from_millis(POLL_INTERVAL as u64)),
+            }
+        }
+    });
+}
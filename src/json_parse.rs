@@ -0,0 +1,225 @@
+use rustc_serialize::json::Json;
+use parse::{Field, TimeFormat, DurationUnit};
+
+/// `Hit` fields that can be fed from a JSON key.
+#[derive(PartialEq, Copy, Clone)]
+enum Target {
+    Host,
+    Time,
+    Request,
+    Method,
+    Path,
+    Query,
+    Protocol,
+    Status,
+    Bytes,
+    Duration,
+    Referer,
+    Agent,
+}
+
+impl Target {
+    fn from_name(name: &str) -> Option<Target> {
+        match name {
+            "host" => Some(Target::Host),
+            "time" => Some(Target::Time),
+            "request" => Some(Target::Request),
+            "method" => Some(Target::Method),
+            "path" => Some(Target::Path),
+            "query" => Some(Target::Query),
+            "protocol" => Some(Target::Protocol),
+            "status" => Some(Target::Status),
+            "bytes" => Some(Target::Bytes),
+            "duration" => Some(Target::Duration),
+            "referer" => Some(Target::Referer),
+            "agent" => Some(Target::Agent),
+            _ => None,
+        }
+    }
+}
+
+/* Keys we look for when nothing else is specified. This covers Caddy, Traefik and the variable
+ * names people usually use with nginx's `escape=json`. Request comes before Method, Path and
+ * Protocol so that more specific values win. Durations are in seconds unless their key is
+ * followed by a unit (s, ms, us or ns), as in "Duration:ns".
+ */
+const DEFAULT_KEYS: [(Target, &'static [&'static str]); 12] = [
+    (Target::Host, &["request.client_ip", "request.remote_ip", "ClientHost", "remote_addr", "client_ip", "remote_ip"]),
+    (Target::Time, &["ts", "time", "time_iso8601", "time_local", "timestamp", "@timestamp", "StartUTC", "msec"]),
+    (Target::Request, &["request"]),
+    (Target::Method, &["request.method", "RequestMethod", "request_method", "method"]),
+    (Target::Path, &["request.uri", "RequestPath", "request_uri", "uri", "path"]),
+    (Target::Query, &["args", "query_string", "query"]),
+    (Target::Protocol, &["request.proto", "RequestProtocol", "server_protocol", "protocol"]),
+    (Target::Status, &["status", "DownstreamStatus", "OriginStatus"]),
+    (Target::Bytes, &["size", "body_bytes_sent", "DownstreamContentSize", "bytes_sent"]),
+    (Target::Duration, &["duration", "request_time", "Duration:ns"]),
+    (Target::Referer, &["request.headers.Referer", "request_Referer", "http_referer", "referer"]),
+    (Target::Agent, &["request.headers.User-Agent", "request_User-Agent", "http_user_agent", "user_agent"]),
+];
+
+/// Returns `json` as a string if it's a scalar.
+///
+/// Arrays are reduced to their first element because that's how Caddy logs header values.
+///
+fn json_to_string(json: &Json) -> Option<String> {
+    match *json {
+        Json::String(ref s) => Some(s.clone()),
+        Json::I64(i) => Some(i.to_string()),
+        Json::U64(i) => Some(i.to_string()),
+        Json::F64(f) => Some(f.to_string()),
+        Json::Boolean(b) => Some(b.to_string()),
+        Json::Array(ref items) => items.first().and_then(json_to_string),
+        Json::Object(_) | Json::Null => None,
+    }
+}
+
+/// Flattens all scalar values in `json` into `result`, with dotted key paths.
+fn flatten(json: &Json, prefix: &str, result: &mut Vec<(String, String)>) {
+    match *json {
+        Json::Object(ref obj) => {
+            for (key, value) in obj.iter() {
+                let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                flatten(value, &path, result);
+            }
+        },
+        _ => {
+            if let Some(s) = json_to_string(json) {
+                result.push((prefix.to_string(), s));
+            }
+        },
+    }
+}
+
+#[derive(Clone)]
+struct KeyPath {
+    keys: Vec<String>,
+    // Only relevant for durations
+    unit: DurationUnit,
+}
+
+impl KeyPath {
+    /// Parses "request.duration:ms" into keys and unit.
+    fn parse(spec: &str) -> KeyPath {
+        let mut parts = spec.rsplitn(2, ':');
+        let last = parts.next().unwrap();
+        let (path, unit) = match (DurationUnit::from_name(last), parts.next()) {
+            (Some(unit), Some(path)) => (path, unit),
+            _ => (spec, DurationUnit::Seconds),
+        };
+        KeyPath {
+            keys: path.split('.').map(|s| s.to_string()).collect(),
+            unit: unit,
+        }
+    }
+}
+
+/// Maps the keys of a JSON log line to `Hit` fields.
+///
+/// Nested keys are expressed with dots, for example `request.remote_ip`.
+///
+#[derive(Clone)]
+pub struct JsonMapping {
+    // For each target, the key paths to look for, in order of preference.
+    keys: Vec<(Target, Vec<KeyPath>)>,
+}
+
+impl JsonMapping {
+    pub fn new() -> JsonMapping {
+        JsonMapping {
+            keys: DEFAULT_KEYS.iter().map(|&(target, paths)| {
+                (target, paths.iter().map(|p| KeyPath::parse(p)).collect())
+            }).collect(),
+        }
+    }
+
+    /// Creates a mapping from a specification such as `host=client.ip,time=@timestamp`.
+    ///
+    /// Fields that aren't mentioned keep their default keys. A field can be given more than one
+    /// key, separated by `|`, in which case the first key found is used.
+    ///
+    pub fn parse(spec: &str) -> Result<JsonMapping, String> {
+        let mut result = JsonMapping::new();
+        for item in spec.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            let mut parts = item.splitn(2, '=');
+            let name = parts.next().unwrap().trim();
+            let keys = match parts.next() {
+                Some(keys) => keys,
+                None => return Err(format!("JSON mapping \"{}\" should be in the form field=key", item)),
+            };
+            let target = match Target::from_name(name) {
+                Some(target) => target,
+                None => return Err(format!("Unknown field \"{}\" in JSON mapping", name)),
+            };
+            for &mut (t, ref mut target_paths) in result.keys.iter_mut() {
+                if t == target {
+                    *target_paths = keys.split('|').map(|k| KeyPath::parse(k.trim())).collect();
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Extracts the values of `line` along with the `Hit` field they belong to.
+    ///
+    /// Values that aren't mapped to anything are returned as extra fields named after their
+    /// dotted key path. Returns an error if `line` isn't a JSON object.
+    ///
+    pub fn extract(&self, line: &str) -> Result<Vec<(Field, String)>, String> {
+        let json = match Json::from_str(line) {
+            Ok(json) => json,
+            Err(e) => return Err(format!("Invalid JSON: {}", e)),
+        };
+        if !json.is_object() {
+            return Err("Not a JSON object".to_string());
+        }
+        let mut result: Vec<(Field, String)> = Vec::new();
+        let mut used_keys: Vec<String> = Vec::new();
+        for &(target, ref paths) in self.keys.iter() {
+            for path in paths.iter() {
+                let path_refs: Vec<&str> = path.keys.iter().map(|s| &s[..]).collect();
+                let found = match json.find_path(&path_refs) {
+                    Some(found) => found,
+                    None => continue,
+                };
+                let value = match json_to_string(found) {
+                    Some(value) => value,
+                    None => continue,
+                };
+                let field = match target {
+                    Target::Host => Field::Host,
+                    Target::Time => {
+                        if found.is_number() {
+                            Field::Time(TimeFormat::Epoch)
+                        } else if value.len() > 10 && value.as_bytes()[10] == b'T' {
+                            Field::Time(TimeFormat::Iso8601)
+                        } else {
+                            Field::Time(TimeFormat::Apache)
+                        }
+                    },
+                    Target::Request => Field::Request,
+                    Target::Method => Field::Method,
+                    Target::Path => Field::Path,
+                    Target::Query => Field::Query,
+                    Target::Protocol => Field::Protocol,
+                    Target::Status => Field::Status,
+                    Target::Bytes => Field::Bytes,
+                    Target::Duration => Field::Duration(path.unit),
+                    Target::Referer => Field::Referer,
+                    Target::Agent => Field::Agent,
+                };
+                result.push((field, value));
+                used_keys.push(path.keys.join("."));
+                break;
+            }
+        }
+        let mut leaves: Vec<(String, String)> = Vec::new();
+        flatten(&json, "", &mut leaves);
+        for (key, value) in leaves.into_iter() {
+            if !used_keys.contains(&key) {
+                result.push((Field::Extra(key), value));
+            }
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,53 @@
+/// Response time statistics for a group of hits.
+///
+/// All durations are in microseconds.
+///
+#[derive(Clone)]
+pub struct LatencyStats {
+    pub count: usize,
+    pub mean: u64,
+    pub p50: u64,
+    pub p95: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+impl LatencyStats {
+    /// Computes stats for `durations`, or returns `None` if it's empty.
+    pub fn from_durations(durations: &mut Vec<u64>) -> Option<LatencyStats> {
+        if durations.is_empty() {
+            return None;
+        }
+        durations.sort();
+        let count = durations.len();
+        let total = durations.iter().fold(0, |acc, d| acc + d);
+        // Nearest-rank percentile
+        let percentile = |p: usize| durations[(count * p + 99) / 100 - 1];
+        Some(LatencyStats {
+            count: count,
+            mean: total / (count as u64),
+            p50: percentile(50),
+            p95: percentile(95),
+            p99: percentile(99),
+            max: durations[count - 1],
+        })
+    }
+
+    pub fn fmt_summary(&self) -> String {
+        format!(
+            "p99 {:>6} p95 {:>6} p50 {:>6} avg {:>6} max {:>6}",
+            fmt_duration(self.p99), fmt_duration(self.p95), fmt_duration(self.p50),
+            fmt_duration(self.mean), fmt_duration(self.max)
+        )
+    }
+}
+
+pub fn fmt_duration(micros: u64) -> String {
+    if micros < 1000 {
+        format!("{}us", micros)
+    } else if micros < 1000000 {
+        format!("{}ms", micros / 1000)
+    } else {
+        format!("{:.1}s", (micros as f64) / 1000000.0)
+    }
+}
@@ -0,0 +1,119 @@
+use std::mem;
+use std::str;
+
+// Longest line we accept, in bytes. Anything longer is most likely garbage.
+const MAX_LINE_LENGTH: usize = 65536;
+
+// How much of an overlong line we keep to show the user
+const OVERLONG_PREVIEW_LENGTH: usize = 200;
+
+/// Reassembles lines out of the chunks of data we read from a source.
+///
+/// A line that's still being written when we read gets split between two reads. We keep its
+/// beginning until we get the rest of it.
+///
+pub struct LineBuffer {
+    partial: Vec<u8>,
+    // Whether we're dropping the rest of an overlong line
+    skipping: bool,
+}
+
+fn preview(line: &[u8]) -> String {
+    let end = if line.len() > OVERLONG_PREVIEW_LENGTH { OVERLONG_PREVIEW_LENGTH } else { line.len() };
+    format!("{}...", decode_lines(line[..end].to_vec()).0)
+}
+
+/// Decodes `data`, whole lines of it, as UTF-8, whatever bytes it holds.
+///
+/// Bytes that aren't valid UTF-8 are replaced with U+FFFD, and so are NUL bytes, which curses
+/// can't display. Returns the decoded lines and how many of them needed replacements.
+///
+pub fn decode_lines(data: Vec<u8>) -> (String, usize) {
+    let data = if data.contains(&0) {
+        data
+    } else {
+        match String::from_utf8(data) {
+            Ok(decoded) => return (decoded, 0),
+            Err(e) => e.into_bytes(),
+        }
+    };
+    let mut decoded = String::with_capacity(data.len());
+    let mut replaced_count = 0;
+    for (index, line) in data.split(|b| *b == b'\n').enumerate() {
+        if index > 0 {
+            decoded.push('\n');
+        }
+        match str::from_utf8(line) {
+            Ok(line) if !line.contains('\0') => decoded.push_str(line),
+            _ => {
+                decoded.push_str(&String::from_utf8_lossy(line).replace('\0', "\u{fffd}"));
+                replaced_count += 1;
+            },
+        }
+    }
+    (decoded, replaced_count)
+}
+
+impl LineBuffer {
+    pub fn new() -> LineBuffer {
+        LineBuffer {
+            partial: Vec::new(),
+            skipping: false,
+        }
+    }
+
+    /// Adds `data` to the buffer and returns the complete lines we now have, newlines included,
+    /// along with how many of them had invalid bytes (see `decode_lines()`).
+    ///
+    /// Lines longer than `MAX_LINE_LENGTH` are dropped. We return the beginning of each of them
+    /// separately.
+    ///
+    pub fn push(&mut self, data: &[u8]) -> (String, usize, Vec<String>) {
+        let mut lines: Vec<u8> = Vec::new();
+        let mut overlong = Vec::new();
+        let mut rest = data;
+        while let Some(index) = rest.iter().position(|b| *b == b'\n') {
+            let piece = &rest[..index];
+            if self.skipping {
+                // That's the end of the line we're dropping.
+                self.skipping = false;
+            }
+            else if self.partial.len() + piece.len() > MAX_LINE_LENGTH {
+                self.partial.extend_from_slice(piece);
+                overlong.push(preview(&self.partial));
+                self.partial.clear();
+            }
+            else {
+                lines.extend_from_slice(&self.partial);
+                lines.extend_from_slice(piece);
+                lines.push(b'\n');
+                self.partial.clear();
+            }
+            rest = &rest[index+1..];
+        }
+        if !self.skipping {
+            self.partial.extend_from_slice(rest);
+            if self.partial.len() > MAX_LINE_LENGTH {
+                overlong.push(preview(&self.partial));
+                self.partial.clear();
+                self.skipping = true;
+            }
+        }
+        let (lines, replaced_count) = decode_lines(lines);
+        (lines, replaced_count, overlong)
+    }
+
+    /// Returns the line we were in the middle of, if any, as a complete line.
+    ///
+    /// That's for when we know that no more data is coming for it.
+    ///
+    pub fn flush(&mut self) -> (String, usize) {
+        self.skipping = false;
+        if self.partial.is_empty() {
+            return (String::new(), 0);
+        }
+        self.partial.push(b'\n');
+        let partial = mem::replace(&mut self.partial, Vec::new());
+        decode_lines(partial)
+    }
+}
@@ -0,0 +1,19 @@
+use std::path::Path;
+
+/// Stands for our inotify watcher where there's no inotify. We never get a watcher, so callers
+/// fall back to polling.
+pub struct FileWatcher;
+
+impl FileWatcher {
+    pub fn new(_path: &Path) -> Option<FileWatcher> {
+        None
+    }
+
+    pub fn for_dir(_dir: &Path) -> Option<FileWatcher> {
+        None
+    }
+
+    pub fn wait(&mut self, _timeout_ms: i32) -> bool {
+        false
+    }
+}
@@ -1,19 +1,520 @@
 use std::str::FromStr;
-use time::{strptime, now};
-use regex::Regex;
-use hit::Hit;
+use std::mem;
+use std::sync::Arc;
+use time::{strptime, at, at_utc, Timespec, Tm};
+use regex::{Regex, quote};
+use hit::{Hit, normalize_host};
+use json_parse::JsonMapping;
+use token_parse::TokenLayout;
 
+pub const COMMON_FORMAT: &'static str = r#"%h %l %u %t "%r" %>s %b"#;
+pub const COMBINED_FORMAT: &'static str = r#"%h %l %u %t "%r" %>s %b "%{Referer}i" "%{User-agent}i""#;
+pub const VHOST_COMBINED_FORMAT: &'static str = r#"%v:%p %h %l %u %t "%r" %>s %O "%{Referer}i" "%{User-Agent}i""#;
+pub const NGINX_COMBINED_FORMAT: &'static str = r#"$remote_addr - $remote_user [$time_local] "$request" $status $body_bytes_sent "$http_referer" "$http_user_agent""#;
+
+pub const NGINX_MAIN_FORMAT: &'static str = r#"$remote_addr - $remote_user [$time_local] "$request" $status $body_bytes_sent "$http_referer" "$http_user_agent" "$http_x_forwarded_for""#;
+
+const DEFAULT_TIME_FORMAT: &'static str = "%d/%b/%Y:%H:%M:%S";
+
+// "9999-12-31T23:59:59Z", the latest epoch time we take. The time crate panics on times that
+// don't fit in a `Tm`.
+const MAX_EPOCH_SECONDS: f64 = 253402300799.0;
+
+#[derive(Clone)]
+pub enum TimeFormat {
+    // "10/Oct/2000:13:55:36 -0700", with or without the brackets
+    Apache,
+    Strftime(String),
+    // "2000-10-10T13:55:36-07:00"
+    Iso8601,
+    // "971211336.123"
+    Epoch,
+}
+
+#[derive(Copy, Clone)]
+pub enum DurationUnit {
+    Seconds,
+    Milliseconds,
+    Microseconds,
+    Nanoseconds,
+}
+
+impl DurationUnit {
+    pub fn from_name(name: &str) -> Option<DurationUnit> {
+        match name {
+            "s" => Some(DurationUnit::Seconds),
+            "ms" => Some(DurationUnit::Milliseconds),
+            "us" => Some(DurationUnit::Microseconds),
+            "ns" => Some(DurationUnit::Nanoseconds),
+            _ => None,
+        }
+    }
+
+    fn to_micros(&self, value: f64) -> u64 {
+        let micros = match *self {
+            DurationUnit::Seconds => value * 1000000.0,
+            DurationUnit::Milliseconds => value * 1000.0,
+            DurationUnit::Microseconds => value,
+            DurationUnit::Nanoseconds => value / 1000.0,
+        };
+        micros as u64
+    }
+}
+
+/// What a value extracted from a log line ends up being in the `Hit`.
+#[derive(Clone)]
+pub enum Field {
+    Host,
+    Time(TimeFormat),
+    // The whole "GET /path HTTP/1.1" request line
+    Request,
+    Method,
+    // Path, possibly followed by a query string
+    Path,
+    Query,
+    Protocol,
+    Status,
+    Bytes,
+    Duration(DurationUnit),
+    Referer,
+    Agent,
+    // Kept in `Hit.extra` under that name
+    Extra(String),
+    Ignored,
+}
+
+/// A piece of a compiled log format.
+#[derive(Clone)]
+pub enum FormatPart {
+    Literal(String),
+    // A field, and the regex pattern its value matches
+    Field(Field, &'static str),
+}
+
+/// Returns the `LogFormat` string associated with `name` if it's one of the nicknames we know.
+///
+/// Those nicknames are the ones defined in Apache's default configuration.
+///
+pub fn format_by_name(name: &str) -> Option<&'static str> {
+    match name {
+        "common" => Some(COMMON_FORMAT),
+        "combined" => Some(COMBINED_FORMAT),
+        "vhost_combined" => Some(VHOST_COMBINED_FORMAT),
+        _ => None,
+    }
+}
+
+/// Returns the capture pattern to use for a field following `literal`.
+///
+/// A field enclosed in quotes or brackets can contain spaces, so we match up to the closing
+/// character. Otherwise, we use `default`.
+///
+fn capture_pattern(literal: &str, default: &'static str) -> &'static str {
+    if literal.ends_with('"') {
+        r#"[^"]*"#
+    } else if literal.ends_with('[') {
+        r"[^\]]*"
+    } else {
+        default
+    }
+}
+
+/// Compiles an Apache `LogFormat` string into literals and fields.
+fn compile_apache_format(format: &str) -> Result<Vec<FormatPart>, String> {
+    let mut parts: Vec<FormatPart> = Vec::new();
+    let mut literal = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            literal.push(c);
+            continue;
+        }
+        // Modifiers such as "<", ">" or status code conditions are of no interest to us.
+        let mut arg: Option<String> = None;
+        let mut directive: Option<char> = None;
+        while let Some(c) = chars.next() {
+            match c {
+                '<' | '>' | '!' | ',' => continue,
+                c if c.is_digit(10) => continue,
+                '{' => {
+                    let mut s = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => s.push(c),
+                            None => return Err(format!("Unclosed {{ in log format \"{}\"", format)),
+                        }
+                    }
+                    arg = Some(s);
+                },
+                c => { directive = Some(c); break; },
+            }
+        }
+        let directive = match directive {
+            Some(d) => d,
+            None => return Err(format!("Log format \"{}\" ends with a lone %", format)),
+        };
+        if directive == '%' {
+            literal.push('%');
+            continue;
+        }
+        let header = arg.clone().map(|s| s.to_lowercase());
+        let (field, subpattern) = match directive {
+            'h' | 'a' => (Field::Host, capture_pattern(&literal, r"\S+")),
+            't' => match arg {
+                Some(fmt) => (Field::Time(TimeFormat::Strftime(fmt)), capture_pattern(&literal, r".+?")),
+                None => (Field::Time(TimeFormat::Apache), capture_pattern(&literal, r"\[[^\]]+\]")),
+            },
+            'r' => (Field::Request, capture_pattern(&literal, r"\S+ \S+(?: \S+)?")),
+            'U' => (Field::Path, capture_pattern(&literal, r"\S+")),
+            'q' => (Field::Query, capture_pattern(&literal, r"\S*")),
+            'm' => (Field::Method, capture_pattern(&literal, r"\S+")),
+            'H' => (Field::Protocol, capture_pattern(&literal, r"\S+")),
+            's' => (Field::Status, r"\d{3}"),
+            'b' | 'B' => (Field::Bytes, r"-|\d+"),
+            'i' => match header.as_ref().map(|s| &s[..]) {
+                Some("referer") => (Field::Referer, capture_pattern(&literal, r"\S+")),
+                Some("user-agent") => (Field::Agent, capture_pattern(&literal, r".+?")),
+                _ => (Field::Ignored, capture_pattern(&literal, r"\S+")),
+            },
+            'o' | 'e' | 'n' | 'C' | 'x' => (Field::Ignored, capture_pattern(&literal, r"\S+")),
+            'D' => (Field::Duration(DurationUnit::Microseconds), r"\d+"),
+            'T' => {
+                let unit = match arg {
+                    Some(ref unit) => match DurationUnit::from_name(unit) {
+                        Some(unit) => unit,
+                        None => return Err(format!("Unsupported unit in %{{{}}}T", unit)),
+                    },
+                    None => DurationUnit::Seconds,
+                };
+                (Field::Duration(unit), r"\d+")
+            },
+            'A' | 'f' | 'I' | 'k' | 'l' | 'L' | 'O' | 'p' | 'P' | 'R'
+                | 'S' | 'u' | 'v' | 'V' | 'X' => {
+                (Field::Ignored, capture_pattern(&literal, r"\S*"))
+            },
+            d => return Err(format!("Unsupported directive %{} in log format \"{}\"", d, format)),
+        };
+        if !literal.is_empty() {
+            parts.push(FormatPart::Literal(mem::replace(&mut literal, String::new())));
+        }
+        parts.push(FormatPart::Field(field, subpattern));
+    }
+    if !literal.is_empty() {
+        parts.push(FormatPart::Literal(literal));
+    }
+    Ok(parts)
+}
+
+/// Extracts the format string from an nginx `log_format` directive.
+///
+/// `log_format main '$remote_addr - ' '"$request"';` becomes `$remote_addr - "$request"`.
+/// Anything that isn't a `log_format` directive is considered to be the format string itself.
+///
+fn extract_nginx_format(directive: &str) -> String {
+    let directive = directive.trim();
+    let directive = if directive.ends_with(';') { &directive[..directive.len()-1] } else { directive };
+    if !directive.starts_with("log_format") {
+        return directive.to_string();
+    }
+    let mut result = String::new();
+    let mut found_quotes = false;
+    let mut chars = directive.chars();
+    while let Some(c) = chars.next() {
+        if c != '\'' && c != '"' {
+            continue;
+        }
+        found_quotes = true;
+        let quote_char = c;
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    if let Some(escaped) = chars.next() {
+                        result.push(escaped);
+                    }
+                },
+                c if c == quote_char => break,
+                c => result.push(c),
+            }
+        }
+    }
+    if !found_quotes {
+        // log_format name [escape=...] $remote_addr ...
+        let words: Vec<&str> = directive.split_whitespace()
+            .skip(2)
+            .skip_while(|w| w.starts_with("escape="))
+            .collect();
+        result = words.join(" ");
+    }
+    result
+}
+
+/// Compiles an nginx `log_format` string into literals and fields.
+///
+/// Variables that don't map to a `Hit` field are kept as extra fields under their own name.
+///
+fn compile_nginx_format(format: &str) -> Result<Vec<FormatPart>, String> {
+    let mut parts: Vec<FormatPart> = Vec::new();
+    let mut literal = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            literal.push(c);
+            continue;
+        }
+        let mut name = String::new();
+        if chars.peek() == Some(&'{') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('}') => break,
+                    Some(c) => name.push(c),
+                    None => return Err(format!("Unclosed {{ in log format \"{}\"", format)),
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_alphanumeric() || c == '_' {
+                    name.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+        }
+        if name.is_empty() {
+            literal.push('$');
+            continue;
+        }
+        let (field, subpattern) = match &name[..] {
+            "remote_addr" => (Field::Host, capture_pattern(&literal, r"\S+")),
+            "time_local" => (Field::Time(TimeFormat::Apache), capture_pattern(&literal, r"\S+ \S+")),
+            "time_iso8601" => (Field::Time(TimeFormat::Iso8601), capture_pattern(&literal, r"\S+")),
+            "msec" => (Field::Time(TimeFormat::Epoch), capture_pattern(&literal, r"\S+")),
+            "request" => (Field::Request, capture_pattern(&literal, r"\S+ \S+(?: \S+)?")),
+            "uri" | "request_uri" | "document_uri" => (Field::Path, capture_pattern(&literal, r"\S+")),
+            "args" | "query_string" => (Field::Query, capture_pattern(&literal, r"\S*")),
+            "request_method" => (Field::Method, capture_pattern(&literal, r"\S+")),
+            "server_protocol" => (Field::Protocol, capture_pattern(&literal, r"\S+")),
+            "status" => (Field::Status, r"\d{3}"),
+            "body_bytes_sent" => (Field::Bytes, r"-|\d+"),
+            "request_time" => (Field::Duration(DurationUnit::Seconds), r"[\d.]+"),
+            "http_referer" => (Field::Referer, capture_pattern(&literal, r"\S+")),
+            "http_user_agent" => (Field::Agent, capture_pattern(&literal, r".+?")),
+            // When several upstreams are tried, their values are listed: "0.100, 0.200 : 0.050".
+            // As such a value has spaces, lines with it are matched with a regex.
+            _ if name.starts_with("upstream_") => {
+                (Field::Extra(name.clone()), capture_pattern(&literal, r"[^ ,]+(?:(?:, | : )[^ ,]+)*"))
+            },
+            _ => (Field::Extra(name.clone()), capture_pattern(&literal, r"\S*")),
+        };
+        if !literal.is_empty() {
+            parts.push(FormatPart::Literal(mem::replace(&mut literal, String::new())));
+        }
+        parts.push(FormatPart::Field(field, subpattern));
+    }
+    if !literal.is_empty() {
+        parts.push(FormatPart::Literal(literal));
+    }
+    Ok(parts)
+}
+
+/// Returns a regex pattern matching `parts` and the field of each of its capture groups, in order.
+fn regex_pattern(parts: &[FormatPart]) -> (String, Vec<Field>) {
+    let mut pattern = String::new();
+    let mut fields: Vec<Field> = Vec::new();
+    for part in parts.iter() {
+        match *part {
+            FormatPart::Literal(ref literal) => pattern.push_str(&quote(literal)),
+            FormatPart::Field(ref field, subpattern) => {
+                pattern.push('(');
+                pattern.push_str(subpattern);
+                pattern.push(')');
+                fields.push(field.clone());
+            },
+        }
+    }
+    (pattern, fields)
+}
+
+/// Parses a UTC offset such as "-0700", "+02:00" or "Z" into seconds.
+fn parse_utc_offset(value: &str) -> Option<i32> {
+    if value == "Z" {
+        return Some(0);
+    }
+    let sign = match value.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => return None,
+    };
+    let digits: String = value[1..].chars().filter(|c| *c != ':').collect();
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_digit(10)) {
+        return None;
+    }
+    let hours: i32 = match FromStr::from_str(&digits[..2]) {
+        Ok(i) => i,
+        Err(_) => return None,
+    };
+    let minutes: i32 = match FromStr::from_str(&digits[2..]) {
+        Ok(i) => i,
+        Err(_) => return None,
+    };
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Returns `naive`, a wall clock time parsed from a log, in UTC.
+///
+/// `utcoff` is the offset that was logged along with the time. Without it, we consider the time
+/// to be in our local timezone.
+///
+pub fn to_utc(naive: Tm, utcoff: Option<i32>) -> Tm {
+    let mut naive = naive;
+    naive.tm_utcoff = 0;
+    let naive_ts = naive.to_timespec();
+    let utcoff = match utcoff {
+        Some(utcoff) => utcoff,
+        None => at(naive_ts).tm_utcoff,
+    };
+    at_utc(Timespec::new(naive_ts.sec - utcoff as i64, 0))
+}
+
+/// Returns the time `secs` seconds after the Unix epoch, in UTC, unless it's out of range.
+pub fn epoch_to_utc(secs: f64) -> Option<Tm> {
+    // Also false for NaN
+    if !(secs >= 0.0 && secs <= MAX_EPOCH_SECONDS) {
+        return None;
+    }
+    Some(at_utc(Timespec::new(secs as i64, 0)))
+}
+
+/// Returns the time in `value`, written as `fmt` says, in UTC.
+pub fn parse_time(value: &str, fmt: &TimeFormat) -> Option<Tm> {
+    match *fmt {
+        TimeFormat::Apache => {
+            // "[10/Oct/2000:13:55:36 -0700]" --> "10/Oct/2000:13:55:36", "-0700"
+            let value = value.trim_matches(|c| c == '[' || c == ']');
+            let mut parts = value.splitn(2, ' ');
+            let naive = match strptime(parts.next().unwrap(), DEFAULT_TIME_FORMAT) {
+                Ok(tm) => tm,
+                Err(_) => return None,
+            };
+            Some(to_utc(naive, parts.next().and_then(parse_utc_offset)))
+        },
+        TimeFormat::Strftime(ref fmt) => {
+            // If there's a %z in there, strptime() takes care of putting it in tm_utcoff
+            match strptime(value, fmt) {
+                Ok(tm) => {
+                    let utcoff = if fmt.contains("%z") { Some(tm.tm_utcoff) } else { None };
+                    Some(to_utc(tm, utcoff))
+                },
+                Err(_) => None,
+            }
+        },
+        TimeFormat::Iso8601 => {
+            // "2000-10-10T13:55:36.123-07:00" --> "2000-10-10T13:55:36", "-07:00"
+            if value.len() < 19 || !value.is_char_boundary(19) {
+                return None;
+            }
+            let naive = match strptime(&value[..19], "%Y-%m-%dT%H:%M:%S") {
+                Ok(tm) => tm,
+                Err(_) => return None,
+            };
+            let rest = &value[19..];
+            // Skip fractional seconds
+            let offset = match rest.find(|c: char| c != '.' && !c.is_digit(10)) {
+                Some(index) => &rest[index..],
+                None => "",
+            };
+            Some(to_utc(naive, parse_utc_offset(offset)))
+        },
+        TimeFormat::Epoch => {
+            let secs: f64 = match FromStr::from_str(value) {
+                Ok(secs) => secs,
+                Err(_) => return None,
+            };
+            epoch_to_utc(secs)
+        },
+    }
+}
+
+/// Splits `value` at its "?" into `hit.path` and `hit.query`.
+pub fn set_path_and_query(hit: &mut Hit, value: &str) {
+    let mut parts = value.splitn(2, '?');
+    hit.path = parts.next().unwrap().to_string();
+    if let Some(query) = parts.next() {
+        hit.query = query.to_string();
+    }
+}
+
+/// Puts `value`, a "GET /path?query HTTP/1.1" request line, in `hit`.
+///
+/// A lone "-" is what Apache and nginx log when there was no request, such as on a 408. We leave
+/// the method and the path empty then.
+///
+pub fn set_request(hit: &mut Hit, value: &str) -> Result<(), String> {
+    if value == "-" {
+        return Ok(());
+    }
+    let mut parts = value.split(' ');
+    hit.method = parts.next().unwrap().to_string();
+    match parts.next() {
+        Some(path) => set_path_and_query(hit, path),
+        None => return Err(format!("Malformed request line \"{}\"", value)),
+    }
+    if let Some(protocol) = parts.next() {
+        hit.protocol = protocol.to_string();
+    }
+    Ok(())
+}
+
+/// Something that turns log lines into hits.
+///
+/// To add a log format, implement this in its own module and add the format to
+/// `formats::FORMATS`.
+///
+pub trait LogParser: Send + Sync {
+    /// Returns the hit logged in `line`, or why `line` can't be parsed.
+    fn try_parse_line(&self, line: &str) -> Result<Hit, String>;
+
+    fn parse_line(&self, line: &str) -> Option<Hit> {
+        self.try_parse_line(line).ok()
+    }
+
+    /// Returns whether `line` has all the fields of our format. That's what we go by when
+    /// detecting the log format.
+    fn parses_fully(&self, line: &str) -> bool {
+        self.try_parse_line(line).is_ok()
+    }
+
+    /// Returns whether `line` is a comment or a header rather than a hit, which we skip without
+    /// rejecting it.
+    fn is_comment(&self, _line: &str) -> bool {
+        false
+    }
+}
+
+/// A parser that can be shared between the main loop and input threads.
+pub type SharedParser = Arc<dyn LogParser>;
+
+#[derive(Clone)]
+enum Matcher {
+    // A regex compiled from a log format, and what each of its capture groups is
+    Regex(Regex, Vec<Field>),
+    // What each token of a line is, for formats of the common log format family
+    Tokens(TokenLayout),
+    Json(JsonMapping),
+}
+
+#[derive(Clone)]
 pub struct Parser {
-    re_main: Regex,
+    matcher: Matcher,
     re_path: Regex,
 }
 
 impl Parser {
-    pub fn new() -> Parser {
+    fn from_matcher(matcher: Matcher) -> Parser {
         Parser {
-            re_main: Regex::new(
-                r#"(\d+\.\d+\.\d+\.\d+) - - \[(.+) \+\d{4}\] "\w+ ([^ ]+) [^ "]+" (\d+) (\d+) "([^"]*)" "([^"]*)""#
-            ).unwrap(),
+            matcher: matcher,
             // Clean the part after the "?"
             re_path: Regex::new(
                 r#"([^\?]+).*"#
@@ -21,33 +522,195 @@ impl Parser {
         }
     }
 
-    pub fn parse_line(&self, line: &str) -> Option<Hit> {
-        let cap = match self.re_main.captures(line) {
-            Some(cap) => cap,
-            None => return None
+    /// Creates a parser for `format`, compiled into `compiled`.
+    ///
+    /// Formats of the common log format family are matched by our tokenizer, which is faster than
+    /// a regex and copes with escaped quotes. We fall back to a regex for the others, or when
+    /// `tokenize` is false.
+    ///
+    fn from_compiled(format: &str, compiled: Result<Vec<FormatPart>, String>, tokenize: bool) -> Result<Parser, String> {
+        let parts = match compiled {
+            Ok(parts) => parts,
+            Err(e) => return Err(e),
         };
-        let path = cap.at(3).unwrap();
-        let cleaned_path = self.re_path.captures(path).unwrap().at(1).unwrap();
-        let referer = cap.at(6).unwrap();
-        let cleaned_referer = self.re_path.captures(referer).unwrap().at(1).unwrap();
-        Some(Hit {
-            host: cap.at(1).unwrap().to_string(),
-            time: match strptime(cap.at(2).unwrap(), "%d/%b/%Y:%H:%M:%S") {
-                Ok(tm) => tm,
-                Err(_) => now()
+        if tokenize {
+            if let Some(layout) = TokenLayout::from_parts(&parts) {
+                return Ok(Parser::from_matcher(Matcher::Tokens(layout)));
+            }
+        }
+        let (pattern, fields) = regex_pattern(&parts);
+        let re_main = match Regex::new(&format!("^{}", pattern)) {
+            Ok(re) => re,
+            Err(e) => return Err(format!("Can't compile log format \"{}\": {}", format, e)),
+        };
+        Ok(Parser::from_matcher(Matcher::Regex(re_main, fields)))
+    }
+
+    /// Creates a parser from an Apache `LogFormat` string such as `%h %l %u %t "%r" %>s %b`.
+    ///
+    /// Apache nicknames ("common", "combined", "vhost_combined") are also accepted.
+    ///
+    pub fn from_apache_format(format: &str) -> Result<Parser, String> {
+        let format = format_by_name(format).unwrap_or(format);
+        Parser::from_compiled(format, compile_apache_format(format), true)
+    }
+
+    /// Same as `from_apache_format()`, but always matches lines with a regex.
+    ///
+    /// That's what we compare our tokenizer to in the "bench" example.
+    ///
+    #[allow(dead_code)]
+    pub fn regex_from_apache_format(format: &str) -> Result<Parser, String> {
+        let format = format_by_name(format).unwrap_or(format);
+        Parser::from_compiled(format, compile_apache_format(format), false)
+    }
+
+    /// Creates a parser from an nginx `log_format` directive, as it appears in nginx.conf.
+    ///
+    /// The bare format string is also accepted, as well as "combined", nginx's predefined format.
+    ///
+    pub fn from_nginx_format(directive: &str) -> Result<Parser, String> {
+        let format = if directive == "combined" {
+            NGINX_COMBINED_FORMAT.to_string()
+        } else {
+            extract_nginx_format(directive)
+        };
+        Parser::from_compiled(&format, compile_nginx_format(&format), true)
+    }
+
+    /// Creates a parser for logs with one JSON object per line.
+    ///
+    /// `mapping` overrides the keys we look for. See `JsonMapping::parse()` for its syntax.
+    ///
+    pub fn from_json_mapping(mapping: Option<&str>) -> Result<Parser, String> {
+        let json_mapping = match mapping {
+            Some(mapping) => match JsonMapping::parse(mapping) {
+                Ok(json_mapping) => json_mapping,
+                Err(e) => return Err(e),
+            },
+            None => JsonMapping::new(),
+        };
+        Ok(Parser::from_matcher(Matcher::Json(json_mapping)))
+    }
+
+    fn clean_path(&self, path: &str) -> String {
+        match self.re_path.captures(path) {
+            Some(cap) => cap.at(1).unwrap().to_string(),
+            None => path.to_string(),
+        }
+    }
+
+    /// Puts `value` in `hit` according to `field`.
+    ///
+    /// Returns an error if `value` is so wrong that the whole line should be rejected.
+    ///
+    fn apply_field(&self, hit: &mut Hit, field: &Field, value: &str) -> Result<(), String> {
+        match *field {
+            Field::Host => {
+                hit.host = match normalize_host(value) {
+                    Some(host) => host,
+                    None => return Err(format!("Invalid host \"{}\"", value)),
+                };
             },
-            status: match FromStr::from_str(cap.at(4).unwrap()) {
-                Ok(i) => i,
-                Err(_) => 999
+            Field::Time(ref fmt) => {
+                if let Some(tm) = parse_time(value, fmt) {
+                    hit.time = tm;
+                }
             },
-            bytes: match FromStr::from_str(cap.at(5).unwrap()) {
-                Ok(i) => i,
-                Err(_) => 0
+            Field::Request => {
+                if let Err(e) = set_request(hit, value) {
+                    return Err(e);
+                }
             },
-            path: cleaned_path.to_string(),
-            referer: cleaned_referer.to_string(),
-            agent: cap.at(7).unwrap().to_string(),
-        })
+            Field::Method => hit.method = value.to_string(),
+            Field::Path => set_path_and_query(hit, value),
+            Field::Query => {
+                // Apache's %q includes the "?", nginx's $args doesn't. "-" means no query.
+                let value = if value.starts_with('?') { &value[1..] } else { value };
+                if !value.is_empty() && value != "-" {
+                    hit.query = value.to_string();
+                }
+            },
+            Field::Protocol => hit.protocol = value.to_string(),
+            Field::Status => {
+                hit.status = match FromStr::from_str(value) {
+                    Ok(i) => i,
+                    Err(_) => 999
+                };
+            },
+            Field::Bytes => {
+                hit.bytes = match FromStr::from_str(value) {
+                    Ok(i) => i,
+                    Err(_) => 0
+                };
+            },
+            Field::Duration(unit) => {
+                let value: Option<f64> = FromStr::from_str(value).ok();
+                hit.duration = value.map(|v| unit.to_micros(v));
+            },
+            Field::Referer => hit.referer = self.clean_path(value),
+            Field::Agent => hit.agent = value.to_string(),
+            Field::Extra(ref name) => {
+                hit.extra.insert(name.clone(), value.to_string());
+            },
+            Field::Ignored => (),
+        }
+        Ok(())
+    }
+
+    fn parse(&self, line: &str, strict: bool) -> Result<Hit, String> {
+        let mut hit = Hit::new();
+        match self.matcher {
+            Matcher::Regex(ref re_main, ref fields) => {
+                let cap = match re_main.captures(line) {
+                    Some(cap) => cap,
+                    None => return Err("Doesn't match the log format".to_string()),
+                };
+                for (index, field) in fields.iter().enumerate() {
+                    let value = match cap.at(index + 1) {
+                        Some(value) => value,
+                        None => continue,
+                    };
+                    if let Err(e) = self.apply_field(&mut hit, field, value) {
+                        return Err(e);
+                    }
+                }
+            },
+            Matcher::Tokens(ref layout) => {
+                let values = match layout.extract(line, strict) {
+                    Ok(values) => values,
+                    Err(e) => return Err(e),
+                };
+                for (field, value) in values.into_iter() {
+                    if let Err(e) = self.apply_field(&mut hit, field, &value) {
+                        return Err(e);
+                    }
+                }
+            },
+            Matcher::Json(ref json_mapping) => {
+                let values = match json_mapping.extract(line) {
+                    Ok(values) => values,
+                    Err(e) => return Err(e),
+                };
+                for (field, value) in values.into_iter() {
+                    if let Err(e) = self.apply_field(&mut hit, &field, &value) {
+                        return Err(e);
+                    }
+                }
+            },
+        }
+        Ok(hit)
     }
 }
 
+impl LogParser for Parser {
+    fn try_parse_line(&self, line: &str) -> Result<Hit, String> {
+        self.parse(line, false)
+    }
+
+    /// Lines can otherwise miss trailing fields, which would make it hard to tell formats of
+    /// the same family apart.
+    fn parses_fully(&self, line: &str) -> bool {
+        self.parse(line, true).is_ok()
+    }
+}
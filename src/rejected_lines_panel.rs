@@ -0,0 +1,62 @@
+use std::collections::VecDeque;
+use ncurses::{newwin, box_, mvwinsnstr, wrefresh};
+
+pub struct RejectedLinesPanel {
+    scry: i32,
+    scrx: i32,
+    visible: bool,
+    total: u32,
+    // (reason, raw line), most recent last
+    lines: Vec<(String, String)>,
+}
+
+impl RejectedLinesPanel {
+    pub fn new(scry: i32, scrx: i32) -> RejectedLinesPanel {
+        RejectedLinesPanel {
+            scry: scry,
+            scrx: scrx,
+            visible: false,
+            total: 0,
+            lines: Vec::new(),
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    pub fn set_lines(&mut self, total: u32, lines: &VecDeque<(String, String)>) {
+        self.total = total;
+        self.lines = lines.iter().cloned().collect();
+    }
+
+    pub fn refresh(&self) {
+        if !self.visible {
+            return
+        }
+        let width = self.scrx - 4;
+        let height = self.scry - 2;
+        let w = newwin(height, width, 1, 2);
+        let header = if self.total > 0 {
+            format!("{} rejected lines. Most recent first:", self.total)
+        } else {
+            "No rejected lines so far.".to_string()
+        };
+        mvwinsnstr(w, 1, 1, &header, width-2);
+        let mut y = 3;
+        for &(ref reason, ref line) in self.lines.iter().rev() {
+            if y + 1 >= height - 1 {
+                break;
+            }
+            mvwinsnstr(w, y, 1, reason, width-2);
+            mvwinsnstr(w, y+1, 3, line, width-4);
+            y += 2;
+        }
+        box_(w, 0, 0);
+        wrefresh(w);
+    }
+}
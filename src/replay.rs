@@ -0,0 +1,215 @@
+use std::cmp::min;
+use time::precise_time_s;
+use hit::{Hit, TimeDisplay, fmt_date_time};
+use parse::epoch_to_utc;
+use visits::{VisitStats, VISIT_TIMEOUT};
+
+// How far, in simulated seconds, the step key moves the clock
+const STEP_SECONDS: f64 = 60.0;
+
+// How far, in simulated seconds, the seek keys move the clock
+pub const SEEK_SECONDS: f64 = 10.0 * 60.0;
+
+// At full speed, how long, in seconds, we feed hits at each tick before getting back to the
+// keyboard and the screen
+const MAX_SPEED_TIME_BUDGET: f64 = 0.05;
+
+// At full speed, how many hits we feed between looks at the time
+const MAX_SPEED_CHUNK_SIZE: usize = 1000;
+
+/// How fast we replay logs.
+#[derive(Copy, Clone)]
+pub enum ReplaySpeed {
+    // Simulated seconds per real second
+    Factor(f64),
+    // As fast as we can
+    Max,
+}
+
+impl ReplaySpeed {
+    /// Parses "max" or a factor such as "10" or "10x".
+    pub fn from_name(name: &str) -> Option<ReplaySpeed> {
+        if name == "max" {
+            return Some(ReplaySpeed::Max);
+        }
+        let factor = if name.ends_with('x') { &name[..name.len()-1] } else { name };
+        match factor.parse::<f64>() {
+            Ok(factor) if factor > 0.0 => Some(ReplaySpeed::Factor(factor)),
+            _ => None,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match *self {
+            ReplaySpeed::Factor(factor) => format!("{}x", factor),
+            ReplaySpeed::Max => "max speed".to_string(),
+        }
+    }
+}
+
+fn hit_seconds(hit: &Hit) -> f64 {
+    hit.time.to_timespec().sec as f64
+}
+
+/// Feeds hits to `VisitStats` at the pace of their own times, following a simulated clock.
+///
+/// We keep all hits, sorted by time, so that we can seek back.
+///
+pub struct Replay {
+    hits: Vec<Hit>,
+    // How many of `hits` were fed, which are those logged at or before `clock`
+    fed_count: usize,
+    // Simulated time, in seconds since the epoch. `None` until our first tick with hits.
+    clock: Option<f64>,
+    speed: ReplaySpeed,
+    paused: bool,
+    // Real time of our last tick
+    last_tick: f64,
+}
+
+impl Replay {
+    pub fn new(speed: ReplaySpeed) -> Replay {
+        Replay {
+            hits: Vec::new(),
+            fed_count: 0,
+            clock: None,
+            speed: speed,
+            paused: false,
+            last_tick: precise_time_s(),
+        }
+    }
+
+    /// Returns the index of the first hit logged after `seconds`.
+    fn index_after(&self, seconds: f64) -> usize {
+        let (mut lo, mut hi) = (0, self.hits.len());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if hit_seconds(&self.hits[mid]) <= seconds { lo = mid + 1 } else { hi = mid }
+        }
+        lo
+    }
+
+    /// Adds `hit` to the replay. If our clock is already past it, it's fed right away.
+    pub fn add(&mut self, hit: Hit, stats: &mut VisitStats) {
+        let seconds = hit_seconds(&hit);
+        let index = self.index_after(seconds);
+        match self.clock {
+            Some(clock) if seconds <= clock => {
+                stats.feed_hit(&hit);
+                self.fed_count += 1;
+            },
+            _ => (),
+        }
+        self.hits.insert(index, hit);
+    }
+
+    fn advance_to(&mut self, clock: f64, stats: &mut VisitStats) {
+        let end = self.index_after(clock);
+        for hit in self.hits[self.fed_count..end].iter() {
+            stats.feed_hit(hit);
+        }
+        self.fed_count = end;
+        self.clock = Some(clock);
+    }
+
+    /// Moves our clock forward according to the time that passed since the last tick.
+    pub fn tick(&mut self, stats: &mut VisitStats) {
+        let now = precise_time_s();
+        let elapsed = now - self.last_tick;
+        self.last_tick = now;
+        let clock = match (self.clock, self.hits.first()) {
+            (Some(clock), _) => clock,
+            (None, Some(hit)) => hit_seconds(hit),
+            (None, None) => return,
+        };
+        let target = match (self.paused, self.speed) {
+            (true, _) => clock,
+            (false, ReplaySpeed::Factor(factor)) => clock + elapsed * factor,
+            (false, ReplaySpeed::Max) => {
+                self.advance_at_max_speed(clock, stats);
+                return;
+            },
+        };
+        self.advance_to(target, stats);
+    }
+
+    /// Feeds hits from `clock` on, moving our clock along, for as long as `MAX_SPEED_TIME_BUDGET`
+    /// allows.
+    fn advance_at_max_speed(&mut self, clock: f64, stats: &mut VisitStats) {
+        let deadline = precise_time_s() + MAX_SPEED_TIME_BUDGET;
+        let mut clock = clock;
+        loop {
+            let end = min(self.fed_count + MAX_SPEED_CHUNK_SIZE, self.hits.len());
+            if end > 0 {
+                clock = clock.max(hit_seconds(&self.hits[end-1]));
+            }
+            self.advance_to(clock, stats);
+            if self.fed_count == self.hits.len() || precise_time_s() >= deadline {
+                return;
+            }
+        }
+    }
+
+    /// Returns whether we're replaying at full speed and have hits left to feed, in which case
+    /// we should be ticked again right away.
+    pub fn is_catching_up(&self) -> bool {
+        match self.speed {
+            ReplaySpeed::Max => !self.paused && self.fed_count < self.hits.len(),
+            ReplaySpeed::Factor(_) => false,
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    /// Pauses and moves our clock a minute forward.
+    pub fn step(&mut self, stats: &mut VisitStats) {
+        self.paused = true;
+        if let Some(clock) = self.clock {
+            self.advance_to(clock + STEP_SECONDS, stats);
+        }
+    }
+
+    /// Moves our clock by `delta` seconds, backwards if negative.
+    ///
+    /// To go backwards, we start over from the hits of visits that were active at that time.
+    ///
+    pub fn seek(&mut self, delta: f64, stats: &mut VisitStats) {
+        let clock = match self.clock {
+            Some(clock) => clock,
+            None => return,
+        };
+        let target = clock + delta;
+        if delta >= 0.0 {
+            self.advance_to(target, stats);
+            return;
+        }
+        stats.clear();
+        let start = self.index_after(target - VISIT_TIMEOUT as f64);
+        let end = self.index_after(target);
+        for hit in self.hits[start..end].iter() {
+            stats.feed_hit(hit);
+        }
+        self.fed_count = end;
+        self.clock = Some(target);
+    }
+
+    pub fn fmt_status(&self, display: TimeDisplay) -> String {
+        let clock = match self.clock {
+            Some(clock) => match epoch_to_utc(clock) {
+                Some(tm) => fmt_date_time(&tm, display),
+                None => format!("{:.0}", clock),
+            },
+            None => return "Replay waiting for hits.".to_string(),
+        };
+        let state = if self.paused {
+            "paused".to_string()
+        } else if self.fed_count == self.hits.len() {
+            "end of log".to_string()
+        } else {
+            self.speed.describe()
+        };
+        format!("Replay {} ({}).", clock, state)
+    }
+}
@@ -1,11 +1,14 @@
 use std::cmp::{min, max};
 use ncurses::{
-    stdscr, getmaxy, getmaxx, mvprintw, erase, refresh, mvaddnstr,
+    stdscr, getmaxy, getmaxx, erase, refresh, mvaddnstr,
     attron, attroff, A_REVERSE
 };
 use visits::Visit;
+use hit::TimeDisplay;
 use help_panel::HelpPanel;
 use visit_detail_panel::VisitDetailPanel;
+use rejected_lines_panel::RejectedLinesPanel;
+use std::collections::VecDeque;
 
 pub struct Screen {
     scrx: u32,
@@ -14,6 +17,7 @@ pub struct Screen {
     maxindex: u32,
     help_panel: HelpPanel,
     visit_detail_panel: VisitDetailPanel,
+    rejected_lines_panel: RejectedLinesPanel,
 }
 
 impl Screen {
@@ -28,6 +32,7 @@ impl Screen {
             maxindex: 0,
             help_panel: HelpPanel::new(scrx),
             visit_detail_panel: VisitDetailPanel::new(scry, scrx),
+            rejected_lines_panel: RejectedLinesPanel::new(scry, scrx),
         }
     }
 
@@ -47,6 +52,10 @@ impl Screen {
             self.help_panel.toggle();
             true
         }
+        else if self.rejected_lines_panel.is_visible() {
+            self.rejected_lines_panel.toggle();
+            true
+        }
         else if self.visit_detail_panel.is_visible() {
             self.visit_detail_panel.close();
             true
@@ -59,6 +68,7 @@ impl Screen {
     pub fn refresh(&self) {
         refresh();
         self.visit_detail_panel.refresh();
+        self.rejected_lines_panel.refresh();
         self.help_panel.refresh();
     }
 
@@ -72,7 +82,8 @@ impl Screen {
     }
 
     pub fn printstatus(&self, msg: &str) {
-        mvprintw((self.scry-1) as i32, 0, msg);
+        // Not printw(): our message can have '%' in it.
+        mvaddnstr((self.scry-1) as i32, 0, msg, self.scrx as i32);
     }
 
     pub fn up(&mut self) {
@@ -96,6 +107,22 @@ impl Screen {
         self.refresh();
     }
 
+    pub fn toggle_rejected_lines(&mut self) {
+        self.rejected_lines_panel.toggle();
+    }
+
+    pub fn is_showing_rejected_lines(&self) -> bool {
+        self.rejected_lines_panel.is_visible()
+    }
+
+    pub fn set_rejected_lines(&mut self, total: u32, lines: &VecDeque<(String, String)>) {
+        self.rejected_lines_panel.set_lines(total, lines);
+    }
+
+    pub fn set_time_display(&mut self, display: TimeDisplay) {
+        self.visit_detail_panel.set_time_display(display);
+    }
+
     pub fn show_visit_details(&mut self, visit: &Visit) {
         self.visit_detail_panel.set_visit(visit)
     }
@@ -0,0 +1,302 @@
+use std::io::prelude::*;
+use std::io;
+use std::fs;
+use std::net::{UdpSocket, TcpListener, TcpStream};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use input::{InputSender, Batch, BATCH_DELAY};
+use line_buffer::decode_lines;
+
+// Biggest message we accept. That's much more than what syslog daemons usually send.
+const MAX_MESSAGE_SIZE: usize = 65536;
+
+/// Where we listen for syslog messages.
+pub enum SyslogAddr {
+    Udp(String),
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl SyslogAddr {
+    /// Parses "udp://HOST:PORT", "tcp://HOST:PORT" or "unix:///PATH".
+    pub fn parse(value: &str) -> Option<SyslogAddr> {
+        if value.starts_with("udp://") {
+            Some(SyslogAddr::Udp(value[6..].to_string()))
+        }
+        else if value.starts_with("tcp://") {
+            Some(SyslogAddr::Tcp(value[6..].to_string()))
+        }
+        else if value.starts_with("unix://") {
+            Some(SyslogAddr::Unix(PathBuf::from(&value[7..])))
+        }
+        else {
+            None
+        }
+    }
+}
+
+/// Returns the first space-separated token of `value`, and what follows it.
+fn next_token(value: &str) -> (&str, &str) {
+    match value.find(' ') {
+        Some(index) => (&value[..index], &value[index+1..]),
+        None => (value, ""),
+    }
+}
+
+/// Returns what follows the structured data part of a RFC 5424 message.
+fn skip_structured_data(value: &str) -> &str {
+    if value.starts_with('-') {
+        return &value[1..];
+    }
+    let mut in_element = false;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for (index, c) in value.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' if in_quotes => escaped = true,
+            '"' if in_element => in_quotes = !in_quotes,
+            '[' if !in_element => in_element = true,
+            ']' if in_element && !in_quotes => in_element = false,
+            _ if !in_element => return &value[index..],
+            _ => (),
+        }
+    }
+    ""
+}
+
+/// Parses a RFC 5424 message, without its "<PRI>1 " prefix.
+fn parse_rfc5424(value: &str) -> (String, String) {
+    let (_timestamp, rest) = next_token(value);
+    let (hostname, rest) = next_token(rest);
+    let (app_name, rest) = next_token(rest);
+    let (_procid, rest) = next_token(rest);
+    let (_msgid, rest) = next_token(rest);
+    let rest = skip_structured_data(rest);
+    let body = if rest.starts_with(' ') { &rest[1..] } else { rest };
+    let body = if body.starts_with('\u{feff}') { &body['\u{feff}'.len_utf8()..] } else { body };
+    let label = match (app_name, hostname) {
+        ("-", "-") => "",
+        ("-", hostname) => hostname,
+        (app_name, _) => app_name,
+    };
+    (label.to_string(), body.to_string())
+}
+
+/// Returns the name of a RFC 3164 tag ("nginx[1234]:" or "nginx:"), if `token` is one.
+fn tag_name(token: &str) -> Option<&str> {
+    if !token.ends_with(':') {
+        return None;
+    }
+    let tag = &token[..token.len()-1];
+    match tag.find('[') {
+        Some(index) => Some(&tag[..index]),
+        None => Some(tag),
+    }
+}
+
+/// Parses a RFC 3164 message, without its "<PRI>" prefix.
+///
+/// The hostname and the tag are both optional: local senders often leave the hostname out, and
+/// nginx leaves both out with "nohostname" and an empty tag. As we can't tell a hostname from the
+/// first word of the body, we only take a word for a hostname when a tag follows it.
+///
+fn parse_rfc3164(value: &str) -> (String, String) {
+    // "Oct 10 13:55:36 "
+    if value.len() < 16 || value.as_bytes()[15] != b' ' {
+        return (String::new(), value.to_string());
+    }
+    let rest = &value[16..];
+    let (first, after_first) = next_token(rest);
+    if let Some(tag) = tag_name(first) {
+        return (tag.to_string(), after_first.to_string());
+    }
+    let (second, after_second) = next_token(after_first);
+    match tag_name(second) {
+        Some(tag) => (tag.to_string(), after_second.to_string()),
+        None => (String::new(), rest.to_string()),
+    }
+}
+
+/// Splits a syslog message into a label for its sender and its body.
+///
+/// The label is the message's tag (its "app name" in RFC 5424) or, without one, its hostname.
+/// Messages that don't look like syslog messages are all body.
+///
+pub fn parse_message(message: &str) -> (String, String) {
+    let message = message.trim_end_matches(|c| c == '\n' || c == '\r' || c == '\0');
+    let pri_end = match message.find('>') {
+        Some(index) if message.starts_with('<') && index <= 4 => index,
+        _ => return (String::new(), message.to_string()),
+    };
+    if !message[1..pri_end].chars().all(|c| c.is_digit(10)) {
+        return (String::new(), message.to_string());
+    }
+    let rest = &message[pri_end+1..];
+    if rest.starts_with("1 ") {
+        parse_rfc5424(&rest[2..])
+    }
+    else {
+        parse_rfc3164(rest)
+    }
+}
+
+fn push_message(data: &[u8], site: &Option<String>, batch: &mut Batch) {
+    // Some senders end their messages with a NUL byte, which isn't an invalid byte for them.
+    let end = match data.iter().rposition(|b| !b"\n\r\0".contains(b)) {
+        Some(index) => index + 1,
+        None => return,
+    };
+    let (message, replaced_count) = decode_lines(data[..end].to_vec());
+    let (label, mut body) = parse_message(&message);
+    if body.trim().is_empty() {
+        return;
+    }
+    body.push('\n');
+    match *site {
+        Some(ref site) => batch.push(site, &body, replaced_count),
+        None => batch.push(&label, &body, replaced_count),
+    }
+}
+
+/// Receives datagrams with `recv` and sends the messages they hold through `tx`.
+///
+/// We wake up regularly to send what we have. When the main loop is behind, we drop messages
+/// rather than let the system drop them while we wait.
+///
+fn receive_datagrams<F>(mut recv: F, site: Option<String>, tx: InputSender)
+    where F: FnMut(&mut [u8]) -> io::Result<usize>
+{
+    let mut buf = vec![0u8; MAX_MESSAGE_SIZE];
+    let mut batch = Batch::new();
+    loop {
+        // Timeouts and other errors just mean there's nothing to read for now.
+        if let Ok(size) = recv(&mut buf) {
+            push_message(&buf[..size], &site, &mut batch);
+        }
+        if batch.is_due() {
+            for msg in batch.take().into_iter() {
+                if !tx.offer(msg) {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn batch_delay() -> Option<Duration> {
+    Some(Duration::from_millis((BATCH_DELAY * 1000.0) as u64))
+}
+
+/// Reads the next message from a TCP stream.
+///
+/// Messages are either prefixed with their length ("octet counting") or end with a newline.
+///
+fn read_tcp_message<R: BufRead>(reader: &mut R, message: &mut Vec<u8>) -> io::Result<usize> {
+    message.clear();
+    let starts_with_digit = match reader.fill_buf() {
+        Ok(buf) if buf.is_empty() => return Ok(0),
+        Ok(buf) => (buf[0] as char).is_digit(10),
+        Err(e) => return Err(e),
+    };
+    if !starts_with_digit {
+        return reader.take(MAX_MESSAGE_SIZE as u64).read_until(b'\n', message);
+    }
+    let mut length = Vec::new();
+    if let Err(e) = reader.take(10).read_until(b' ', &mut length) {
+        return Err(e);
+    }
+    let length: usize = match String::from_utf8_lossy(&length).trim().parse() {
+        Ok(length) if length <= MAX_MESSAGE_SIZE => length,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid message length")),
+    };
+    message.resize(length, 0);
+    match reader.read_exact(message) {
+        Ok(()) => Ok(length),
+        Err(e) => Err(e),
+    }
+}
+
+fn handle_tcp_connection(stream: TcpStream, site: Option<String>, tx: InputSender) {
+    thread::spawn(move || {
+        let mut reader = io::BufReader::new(stream);
+        let mut message = Vec::new();
+        let mut batch = Batch::new();
+        loop {
+            let ended = match read_tcp_message(&mut reader, &mut message) {
+                Ok(0) | Err(_) => true,
+                Ok(_) => {
+                    push_message(&message, &site, &mut batch);
+                    false
+                },
+            };
+            // The sender waits for us when we're behind, so we send what we have before we
+            // wait for more.
+            if ended || reader.buffer().is_empty() || batch.is_due() {
+                if !tx.send_all(batch.take()) || ended {
+                    return;
+                }
+            }
+        }
+    });
+}
+
+/// Listens for syslog messages at `addr` in a thread and sends their bodies through `tx`.
+///
+/// Each message goes under the `site` name if given, or under the label of its sender.
+/// We bind right away so that we can report errors before starting.
+///
+pub fn spawn_syslog_listener(addr: &SyslogAddr, site: Option<String>, tx: InputSender) -> io::Result<()> {
+    match *addr {
+        SyslogAddr::Udp(ref addr) => {
+            let socket = match UdpSocket::bind(&addr[..]) {
+                Ok(socket) => socket,
+                Err(e) => return Err(e),
+            };
+            if let Err(e) = socket.set_read_timeout(batch_delay()) {
+                return Err(e);
+            }
+            thread::spawn(move || {
+                receive_datagrams(|buf| socket.recv_from(buf).map(|(size, _)| size), site, tx);
+            });
+        },
+        SyslogAddr::Tcp(ref addr) => {
+            let listener = match TcpListener::bind(&addr[..]) {
+                Ok(listener) => listener,
+                Err(e) => return Err(e),
+            };
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if let Ok(stream) = stream {
+                        handle_tcp_connection(stream, site.clone(), tx.clone());
+                    }
+                }
+            });
+        },
+        SyslogAddr::Unix(ref path) => {
+            // A socket left over by a previous run would prevent us from binding.
+            if let Ok(metadata) = fs::symlink_metadata(path) {
+                if metadata.file_type().is_socket() {
+                    let _ = fs::remove_file(path);
+                }
+            }
+            let socket = match UnixDatagram::bind(path) {
+                Ok(socket) => socket,
+                Err(e) => return Err(e),
+            };
+            if let Err(e) = socket.set_read_timeout(batch_delay()) {
+                return Err(e);
+            }
+            thread::spawn(move || {
+                receive_datagrams(|buf| socket.recv(buf), site, tx);
+            });
+        },
+    }
+    Ok(())
+}
@@ -0,0 +1,405 @@
+use std::io::prelude::*;
+use std::io;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use time::Timespec;
+use parse::{LogParser, SharedParser};
+use formats::detect_format;
+
+// How much we read from the end of the file the first time we open it, by default
+const BACKFILL_SIZE: u64 = 90000;
+
+// Size of the chunks we read when going backwards through the file
+const SEARCH_CHUNK_SIZE: u64 = 8192;
+
+// When looking for a time, we bisect the file until we're down to that many bytes, then scan lines.
+const BISECT_MIN_SPAN: u64 = 65536;
+
+// How many lines we try after a bisection point before giving up on finding a time there
+const BISECT_MAX_LINES: usize = 20;
+
+// How much we read from the start of the file to detect its format, when we have to
+const DETECTION_READ_SIZE: u64 = 65536;
+
+// How much we read from a file at once, at most
+const READ_SIZE: u64 = 65536;
+
+/// Where we start reading a file the first time we open it.
+///
+/// Except with `Start`, we always start at the beginning of a line.
+///
+#[derive(Clone)]
+pub enum Backfill {
+    // That many bytes from the end
+    Bytes(u64),
+    Start,
+    // That many lines from the end
+    Lines(usize),
+    // The first line logged at or after that time. We need a parser to read times from lines.
+    // Without one, we detect the format from the start of the file.
+    Since(Timespec, Option<SharedParser>),
+}
+
+impl Default for Backfill {
+    fn default() -> Backfill {
+        Backfill::Bytes(BACKFILL_SIZE)
+    }
+}
+
+/// Returns the position of the first line starting at or after `pos`.
+///
+/// If there's no such line yet, that's the end of the file.
+///
+fn skip_partial_line(fp: &mut fs::File, pos: u64) -> io::Result<u64> {
+    if pos == 0 {
+        return Ok(0);
+    }
+    if let Err(e) = fp.seek(io::SeekFrom::Start(pos - 1)) {
+        return Err(e);
+    }
+    // If the byte before `pos` is a newline, we're already at the start of a line.
+    let mut line = Vec::new();
+    match io::BufReader::new(&mut *fp).read_until(b'\n', &mut line) {
+        Ok(read_size) => Ok(pos - 1 + read_size as u64),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns the position of the `count`th line from the end of the file.
+fn find_last_lines(fp: &mut fs::File, len: u64, count: usize) -> io::Result<u64> {
+    if count == 0 {
+        return Ok(len);
+    }
+    let mut found = 0;
+    let mut end = len;
+    let mut buf = vec![0u8; SEARCH_CHUNK_SIZE as usize];
+    while end > 0 {
+        let start = if end > SEARCH_CHUNK_SIZE { end - SEARCH_CHUNK_SIZE } else { 0 };
+        let chunk = &mut buf[..(end - start) as usize];
+        if let Err(e) = fp.seek(io::SeekFrom::Start(start)) {
+            return Err(e);
+        }
+        if let Err(e) = fp.read_exact(chunk) {
+            return Err(e);
+        }
+        for (index, byte) in chunk.iter().enumerate().rev() {
+            let offset = start + index as u64;
+            // The newline ending the last line doesn't start a new line.
+            if *byte == b'\n' && offset != len - 1 {
+                found += 1;
+                if found == count {
+                    return Ok(offset + 1);
+                }
+            }
+        }
+        end = start;
+    }
+    Ok(0)
+}
+
+fn line_time(line: &[u8], parser: &dyn LogParser) -> Option<Timespec> {
+    let line = String::from_utf8_lossy(line);
+    parser.parse_line(line.trim_end()).map(|hit| hit.time.to_timespec())
+}
+
+/// Returns the position and time of the first line with a time after `pos`, if we can find one
+/// close enough.
+fn first_line_time_after(fp: &mut fs::File, pos: u64, parser: &dyn LogParser) -> io::Result<Option<(u64, Timespec)>> {
+    let mut offset = match skip_partial_line(fp, pos) {
+        Ok(offset) => offset,
+        Err(e) => return Err(e),
+    };
+    if let Err(e) = fp.seek(io::SeekFrom::Start(offset)) {
+        return Err(e);
+    }
+    let mut reader = io::BufReader::new(&mut *fp);
+    let mut line = Vec::new();
+    for _ in 0..BISECT_MAX_LINES {
+        line.clear();
+        let read_size = match reader.read_until(b'\n', &mut line) {
+            Ok(read_size) => read_size,
+            Err(e) => return Err(e),
+        };
+        if read_size == 0 || line[read_size-1] != b'\n' {
+            break;
+        }
+        if let Some(time) = line_time(&line, parser) {
+            return Ok(Some((offset, time)));
+        }
+        offset += read_size as u64;
+    }
+    Ok(None)
+}
+
+/// Returns the position of the first line logged at or after `since`.
+///
+/// We expect the file to be sorted by time, which logs are, give or take a few seconds. We bisect
+/// the file to get close to `since`, then we read lines from there.
+///
+fn find_time(fp: &mut fs::File, len: u64, since: Timespec, parser: &dyn LogParser) -> io::Result<u64> {
+    // `lo` is always the start of a line logged before `since`, or the start of the file.
+    let mut lo = 0;
+    let mut hi = len;
+    while lo + BISECT_MIN_SPAN < hi {
+        let mid = lo + (hi - lo) / 2;
+        match first_line_time_after(fp, mid, parser) {
+            Ok(Some((line_start, time))) if time < since => lo = line_start,
+            Ok(_) => hi = mid,
+            Err(e) => return Err(e),
+        }
+    }
+    if let Err(e) = fp.seek(io::SeekFrom::Start(lo)) {
+        return Err(e);
+    }
+    let mut reader = io::BufReader::new(&mut *fp);
+    let mut line = Vec::new();
+    let mut offset = lo;
+    loop {
+        line.clear();
+        let read_size = match reader.read_until(b'\n', &mut line) {
+            Ok(read_size) => read_size,
+            Err(e) => return Err(e),
+        };
+        // We stop at the end of the file or at its incomplete last line, which we'll read later.
+        if read_size == 0 || line[read_size-1] != b'\n' {
+            return Ok(offset);
+        }
+        match line_time(&line, parser) {
+            Some(time) if time >= since => return Ok(offset),
+            _ => offset += read_size as u64,
+        }
+    }
+}
+
+/// Detects the log format of `fp` from its first lines.
+fn detect_file_format(fp: &mut fs::File) -> io::Result<Option<SharedParser>> {
+    if let Err(e) = fp.seek(io::SeekFrom::Start(0)) {
+        return Err(e);
+    }
+    let mut contents = Vec::new();
+    if let Err(e) = fp.take(DETECTION_READ_SIZE).read_to_end(&mut contents) {
+        return Err(e);
+    }
+    let contents = String::from_utf8_lossy(&contents);
+    let mut lines: Vec<String> = contents.split('\n').map(|l| l.to_string()).collect();
+    // The last one is either empty or incomplete.
+    lines.pop();
+    lines.retain(|l| !l.trim().is_empty());
+    Ok(detect_format(&lines).map(|(_, parser)| parser))
+}
+
+/// Returns where `backfill` tells us to start reading `fp`, which is `len` bytes long.
+fn start_position(fp: &mut fs::File, len: u64, backfill: &Backfill) -> io::Result<u64> {
+    match *backfill {
+        Backfill::Start => Ok(0),
+        Backfill::Bytes(size) => {
+            if len <= size { Ok(0) } else { skip_partial_line(fp, len - size) }
+        },
+        Backfill::Lines(count) => find_last_lines(fp, len, count),
+        Backfill::Since(since, ref parser) => {
+            let parser = match *parser {
+                Some(ref parser) => Some(parser.clone()),
+                None => match detect_file_format(fp) {
+                    Ok(parser) => parser,
+                    Err(e) => return Err(e),
+                },
+            };
+            match parser {
+                Some(parser) => find_time(fp, len, since, &*parser),
+                // We can't read times, so we do as usual.
+                None => start_position(fp, len, &Backfill::default()),
+            }
+        },
+    }
+}
+
+/// Something that happened to the file we follow, other than being appended to.
+pub enum TailEvent {
+    // Moved away and replaced by a new file (logrotate's default behavior)
+    Rotated,
+    // Emptied in place (logrotate's "copytruncate")
+    Truncated,
+    Deleted,
+    // Came back after having been deleted
+    Reopened,
+}
+
+impl TailEvent {
+    pub fn describe(&self, path: &Path) -> String {
+        match *self {
+            TailEvent::Rotated => format!("{} was rotated, now reading the new file.", path.display()),
+            TailEvent::Truncated => format!("{} was truncated, reading it from the start.", path.display()),
+            TailEvent::Deleted => format!("{} is gone, waiting for it to come back.", path.display()),
+            TailEvent::Reopened => format!("{} is back.", path.display()),
+        }
+    }
+}
+
+/// Follows a log file, like `tail -F` does.
+///
+/// We hold on to an open handle to the file so that when it's rotated, we can still read what was
+/// written to it before the rotation. We then switch to the new file.
+///
+pub struct FileTailer {
+    path: PathBuf,
+    fp: Option<fs::File>,
+    inode: u64,
+    pos: u64,
+    opened_once: bool,
+    // Where we start reading the first time we open the file. Afterwards, we read from the start.
+    backfill: Backfill,
+    // Whether what we've returned so far ends with a complete line
+    at_line_start: bool,
+    // Whether our last read stopped before the end of the file
+    more: bool,
+}
+
+/// Reads what `fp` has left, up to `READ_SIZE` bytes, into `contents`.
+///
+/// Returns whether we stopped before the end.
+///
+fn read_chunk(fp: &mut fs::File, contents: &mut Vec<u8>) -> io::Result<bool> {
+    Read::by_ref(fp).take(READ_SIZE).read_to_end(contents).map(|size| size as u64 == READ_SIZE)
+}
+
+impl FileTailer {
+    pub fn new(path: &Path, backfill: Backfill) -> FileTailer {
+        FileTailer {
+            path: path.to_path_buf(),
+            fp: None,
+            inode: 0,
+            pos: 0,
+            opened_once: false,
+            backfill: backfill,
+            at_line_start: true,
+            more: false,
+        }
+    }
+
+    /// Ends the line we were in the middle of, if any, in `contents`.
+    ///
+    /// We do that when we stop reading a file: nothing more will be appended to its last line, and
+    /// we don't want it to be glued to the first line of what we read next.
+    ///
+    fn end_line(&self, contents: &mut Vec<u8>) {
+        let in_line = match contents.last() {
+            Some(last) => *last != b'\n',
+            None => !self.at_line_start,
+        };
+        if in_line {
+            contents.push(b'\n');
+        }
+    }
+
+    fn open(&mut self, metadata: &fs::Metadata) -> io::Result<()> {
+        let mut fp = match fs::File::open(&self.path) {
+            Ok(fp) => fp,
+            Err(e) => return Err(e),
+        };
+        self.pos = if self.opened_once {
+            0
+        } else {
+            match start_position(&mut fp, metadata.len(), &self.backfill) {
+                Ok(pos) => pos,
+                Err(e) => return Err(e),
+            }
+        };
+        if let Err(e) = fp.seek(io::SeekFrom::Start(self.pos)) {
+            return Err(e);
+        }
+        self.inode = metadata.ino();
+        self.fp = Some(fp);
+        self.opened_once = true;
+        Ok(())
+    }
+
+    /// Returns whether there's more to read right away, because our last read stopped before the
+    /// end of the file.
+    pub fn has_more(&self) -> bool {
+        self.more
+    }
+
+    /// Returns what was appended to the file since the last call, and what happened to it if
+    /// anything special did.
+    ///
+    /// We read one chunk at a time, so that a big file doesn't end up in memory all at once. When
+    /// `has_more()`, call us again.
+    ///
+    pub fn read(&mut self) -> io::Result<(Vec<u8>, Option<TailEvent>)> {
+        let mut contents = Vec::new();
+        let result = self.read_into(&mut contents);
+        if let Some(last) = contents.last() {
+            self.at_line_start = *last == b'\n';
+        }
+        result.map(|event| (contents, event))
+    }
+
+    fn read_into(&mut self, contents: &mut Vec<u8>) -> io::Result<Option<TailEvent>> {
+        self.more = false;
+        let metadata = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                // Whatever was written before the deletion is still readable from our handle.
+                return match self.fp.take() {
+                    Some(mut fp) => {
+                        match read_chunk(&mut fp, contents) {
+                            Ok(true) => {
+                                self.fp = Some(fp);
+                                self.more = true;
+                                Ok(None)
+                            },
+                            Ok(false) => {
+                                self.end_line(contents);
+                                Ok(Some(TailEvent::Deleted))
+                            },
+                            Err(e) => Err(e),
+                        }
+                    },
+                    None => Ok(None),
+                };
+            },
+        };
+        let mut event = None;
+        if self.fp.is_some() && metadata.ino() != self.inode {
+            let mut fp = self.fp.take().unwrap();
+            match read_chunk(&mut fp, contents) {
+                Ok(true) => {
+                    // We finish the old file before switching to the new one.
+                    self.fp = Some(fp);
+                    self.more = true;
+                    return Ok(None);
+                },
+                Ok(false) => (),
+                Err(e) => return Err(e),
+            }
+            self.end_line(contents);
+            event = Some(TailEvent::Rotated);
+        }
+        if self.fp.is_none() {
+            if self.opened_once && event.is_none() {
+                event = Some(TailEvent::Reopened);
+            }
+            if let Err(e) = self.open(&metadata) {
+                return Err(e);
+            }
+        }
+        if metadata.len() < self.pos {
+            self.end_line(contents);
+            if let Err(e) = self.fp.as_mut().unwrap().seek(io::SeekFrom::Start(0)) {
+                return Err(e);
+            }
+            event = Some(TailEvent::Truncated);
+        }
+        let fp = self.fp.as_mut().unwrap();
+        self.more = match read_chunk(fp, contents) {
+            Ok(more) => more,
+            Err(e) => return Err(e),
+        };
+        self.pos = match fp.seek(io::SeekFrom::Current(0)) {
+            Ok(pos) => pos,
+            Err(e) => return Err(e),
+        };
+        Ok(event)
+    }
+}
@@ -0,0 +1,320 @@
+use std::borrow::Cow;
+use parse::{Field, FormatPart};
+
+// Patterns of fields that never match spaces, which we can read as a bare token
+const BARE_PATTERNS: [&'static str; 6] = [r"\S+", r"\S*", r"\d{3}", r"-|\d+", r"\d+", r"[\d.]+"];
+
+// Pattern of Apache's %t, which comes with its brackets
+const BRACKETED_TIME_PATTERN: &'static str = r"\[[^\]]+\]";
+
+/// How a token is delimited in a log line.
+#[derive(PartialEq, Copy, Clone)]
+enum Delimiter {
+    // Up to the next space
+    Space,
+    Quotes,
+    Brackets,
+}
+
+/// Returns how `part`, a piece of a token, starts and ends if it's a literal.
+fn literal_ends(part: &FormatPart) -> (Option<char>, Option<char>) {
+    match *part {
+        FormatPart::Literal(ref literal) => (literal.chars().next(), literal.chars().last()),
+        FormatPart::Field(..) => (None, None),
+    }
+}
+
+/// Returns how the token matching `parts` is delimited and what it holds, if we can read it.
+fn token_field(parts: &[FormatPart]) -> Option<(Delimiter, Field)> {
+    match parts {
+        &[FormatPart::Field(ref field, subpattern)] => {
+            if subpattern == BRACKETED_TIME_PATTERN {
+                Some((Delimiter::Brackets, field.clone()))
+            } else if BARE_PATTERNS.contains(&subpattern) {
+                Some((Delimiter::Space, field.clone()))
+            } else {
+                None
+            }
+        },
+        &[FormatPart::Literal(ref open), FormatPart::Field(ref field, _), FormatPart::Literal(ref close)] => {
+            match (&open[..], &close[..]) {
+                ("\"", "\"") => Some((Delimiter::Quotes, field.clone())),
+                ("[", "]") => Some((Delimiter::Brackets, field.clone())),
+                _ => None,
+            }
+        },
+        _ => {
+            // Tokens such as nginx's "-" or Apache's "%v:%p" don't hold anything we use.
+            let all_ignored = parts.iter().all(|part| match *part {
+                FormatPart::Literal(_) => true,
+                FormatPart::Field(Field::Ignored, subpattern) => BARE_PATTERNS.contains(&subpattern),
+                FormatPart::Field(..) => false,
+            });
+            if !all_ignored {
+                return None;
+            }
+            let delimiter = match (literal_ends(&parts[0]).0, literal_ends(&parts[parts.len()-1]).1) {
+                (Some('"'), Some('"')) => Delimiter::Quotes,
+                (Some('['), Some(']')) => Delimiter::Brackets,
+                _ => Delimiter::Space,
+            };
+            Some((delimiter, Field::Ignored))
+        },
+    }
+}
+
+/// Returns `raw`, quoted text from a log line, without its escapes.
+///
+/// Apache escapes quotes and backslashes with a backslash, and nginx escapes them as `\xHH`.
+/// Other escapes, which stand for control characters or bytes that aren't ASCII, are kept as
+/// they are.
+///
+fn unescape(raw: &str) -> String {
+    let mut result = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('"') => result.push('"'),
+            Some('\\') => result.push('\\'),
+            Some('x') => {
+                let hex: String = chars.clone().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) if hex.len() == 2 && byte >= 0x20 && byte < 0x7f => {
+                        result.push(byte as char);
+                        chars.nth(1);
+                    },
+                    _ => result.push_str("\\x"),
+                }
+            },
+            Some(c) => {
+                result.push('\\');
+                result.push(c);
+            },
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+/// Reads the first token of `line`, which is either text in quotes, text in brackets or a run of
+/// non-space characters.
+///
+/// Returns how the token was delimited, its value and what follows it, or `None` if there's
+/// nothing left to read.
+///
+fn next_token(line: &str) -> Result<Option<(Delimiter, Cow<'_, str>, &str)>, String> {
+    let line = line.trim_start_matches(' ');
+    let bytes = line.as_bytes();
+    match bytes.first() {
+        None => Ok(None),
+        Some(&b'"') => {
+            let mut index = 1;
+            let mut escaped = false;
+            loop {
+                match bytes.get(index) {
+                    None => return Err("Unclosed quote".to_string()),
+                    Some(&b'"') => break,
+                    Some(&b'\\') => {
+                        escaped = true;
+                        index += 2;
+                    },
+                    Some(_) => index += 1,
+                }
+            }
+            let raw = &line[1..index];
+            let value = if escaped { Cow::Owned(unescape(raw)) } else { Cow::Borrowed(raw) };
+            Ok(Some((Delimiter::Quotes, value, &line[index+1..])))
+        },
+        Some(&b'[') => {
+            match line.find(']') {
+                Some(index) => Ok(Some((Delimiter::Brackets, Cow::Borrowed(&line[1..index]), &line[index+1..]))),
+                None => Err("Unclosed bracket".to_string()),
+            }
+        },
+        Some(_) => {
+            let end = line.find(' ').unwrap_or(line.len());
+            Ok(Some((Delimiter::Space, Cow::Borrowed(&line[..end]), &line[end..])))
+        },
+    }
+}
+
+/// Splits `line` into the values of its tokens, each of them either bare, in quotes or in
+/// brackets, the way `next_token()` reads them.
+pub fn split_tokens(line: &str) -> Result<Vec<Cow<'_, str>>, String> {
+    let mut result = Vec::new();
+    let mut rest = line;
+    loop {
+        match next_token(rest) {
+            Ok(Some((_, value, after))) => {
+                result.push(value);
+                rest = after;
+            },
+            Ok(None) => return Ok(result),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Returns whether `value` looks like what `field` should hold, as the regex we replace would
+/// have checked.
+fn is_valid(field: &Field, value: &str) -> bool {
+    let is_number = |value: &str| !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit());
+    match *field {
+        Field::Status => value.len() == 3 && is_number(value),
+        Field::Bytes => value == "-" || is_number(value),
+        Field::Duration(_) => value == "-" || (!value.is_empty() && value.bytes().all(|b| b.is_ascii_digit() || b == b'.')),
+        _ => true,
+    }
+}
+
+/// A log format of the common log format family, which we read token by token.
+///
+/// That's a format made of values separated by spaces, each of them either bare, in quotes or
+/// in brackets, such as Apache's "combined" or nginx's "main".
+///
+#[derive(Clone)]
+pub struct TokenLayout {
+    // How each token is delimited, and what it holds
+    tokens: Vec<(Delimiter, Field)>,
+    // How many tokens a line must have, up to the status. Those after it can be missing.
+    required_count: usize,
+}
+
+impl TokenLayout {
+    /// Returns the layout of the format compiled into `parts`, if it's of the common log format
+    /// family.
+    pub fn from_parts(parts: &[FormatPart]) -> Option<TokenLayout> {
+        // Split the format at its spaces, into the parts of each token.
+        let mut token_parts: Vec<Vec<FormatPart>> = vec![Vec::new()];
+        for part in parts.iter() {
+            match *part {
+                FormatPart::Literal(ref literal) => {
+                    for (index, piece) in literal.split(' ').enumerate() {
+                        if index > 0 {
+                            token_parts.push(Vec::new());
+                        }
+                        if !piece.is_empty() {
+                            token_parts.last_mut().unwrap().push(FormatPart::Literal(piece.to_string()));
+                        }
+                    }
+                },
+                FormatPart::Field(..) => token_parts.last_mut().unwrap().push(part.clone()),
+            }
+        }
+        let mut tokens = Vec::new();
+        for parts in token_parts.iter().filter(|parts| !parts.is_empty()) {
+            match token_field(parts) {
+                Some(token) => tokens.push(token),
+                None => return None,
+            }
+        }
+        let required_count = match tokens.iter().position(|token| match token.1 { Field::Status => true, _ => false }) {
+            Some(index) => index + 1,
+            None => tokens.len(),
+        };
+        Some(TokenLayout {
+            tokens: tokens,
+            required_count: required_count,
+        })
+    }
+
+    /// Extracts the values of `line` along with the `Hit` field they belong to.
+    ///
+    /// Unless we're `strict`, trailing fields after the status can be missing. `-` stands for
+    /// a missing value, quoted or not. Tokens after the ones we know of are ignored.
+    ///
+    pub fn extract<'a, 'b>(&'a self, line: &'b str, strict: bool) -> Result<Vec<(&'a Field, Cow<'b, str>)>, String> {
+        let mut result = Vec::with_capacity(self.tokens.len());
+        let mut rest = line;
+        for (index, &(delimiter, ref field)) in self.tokens.iter().enumerate() {
+            let (found, value, after) = match next_token(rest) {
+                Ok(Some(token)) => token,
+                Ok(None) if index >= self.required_count && !strict => break,
+                Ok(None) => return Err("Missing fields".to_string()),
+                Err(e) => return Err(e),
+            };
+            let placeholder = found == Delimiter::Space && delimiter == Delimiter::Quotes && value == "-";
+            if (found != delimiter && !placeholder) || !is_valid(field, &value) {
+                return Err("Doesn't match the log format".to_string());
+            }
+            rest = after;
+            if let Field::Ignored = *field {
+                continue;
+            }
+            result.push((field, value));
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parse::{Field, FormatPart};
+    use super::{TokenLayout, split_tokens};
+
+    // r#"%h "%r" %>s "%{Referer}i""#
+    fn layout() -> TokenLayout {
+        let parts = vec![
+            FormatPart::Field(Field::Host, r"\S+"),
+            FormatPart::Literal(" \"".to_string()),
+            FormatPart::Field(Field::Request, r".*?"),
+            FormatPart::Literal("\" ".to_string()),
+            FormatPart::Field(Field::Status, r"\d{3}"),
+            FormatPart::Literal(" \"".to_string()),
+            FormatPart::Field(Field::Referer, r".*?"),
+            FormatPart::Literal("\"".to_string()),
+        ];
+        TokenLayout::from_parts(&parts).unwrap()
+    }
+
+    fn extract(line: &str, strict: bool) -> Result<Vec<String>, String> {
+        layout().extract(line, strict).map(|values| values.into_iter().map(|(_, value)| value.into_owned()).collect())
+    }
+
+    #[test]
+    fn unescapes_quotes_and_backslashes() {
+        let tokens = split_tokens(r#""say \"hi\"" "C:\\temp""#).unwrap();
+        assert_eq!(tokens, vec![r#"say "hi""#, r"C:\temp"]);
+    }
+
+    #[test]
+    fn unescapes_printable_hex_escapes_only() {
+        let tokens = split_tokens(r#""\x22quoted\x22 \x0a \xZZ""#).unwrap();
+        assert_eq!(tokens, vec![r#""quoted" \x0a \xZZ"#]);
+    }
+
+    #[test]
+    fn reads_brackets_and_bare_tokens() {
+        let tokens = split_tokens("1.2.3.4 [10/Oct/2000:13:55:36 -0700]  200").unwrap();
+        assert_eq!(tokens, vec!["1.2.3.4", "10/Oct/2000:13:55:36 -0700", "200"]);
+    }
+
+    #[test]
+    fn takes_a_bare_dash_for_a_quoted_value() {
+        let values = extract(r#"1.2.3.4 "GET / HTTP/1.1" 200 -"#, true).unwrap();
+        assert_eq!(values, vec!["1.2.3.4", "GET / HTTP/1.1", "200", "-"]);
+        let values = extract(r#"1.2.3.4 "GET / HTTP/1.1" 200 "-""#, true).unwrap();
+        assert_eq!(values, vec!["1.2.3.4", "GET / HTTP/1.1", "200", "-"]);
+        assert!(extract(r#"1.2.3.4 "GET / HTTP/1.1" 200 http://example.com/"#, true).is_err());
+    }
+
+    #[test]
+    fn allows_missing_fields_after_the_status() {
+        let values = extract(r#"1.2.3.4 "GET / HTTP/1.1" 200"#, false).unwrap();
+        assert_eq!(values, vec!["1.2.3.4", "GET / HTTP/1.1", "200"]);
+        assert!(extract(r#"1.2.3.4 "GET / HTTP/1.1" 200"#, true).is_err());
+        assert!(extract(r#"1.2.3.4 "GET / HTTP/1.1""#, false).is_err());
+    }
+
+    #[test]
+    fn rejects_unclosed_quotes_and_brackets() {
+        assert_eq!(split_tokens(r#"1.2.3.4 "GET / HTTP/1.1"#), Err("Unclosed quote".to_string()));
+        assert_eq!(split_tokens(r#"1.2.3.4 "GET / \""#), Err("Unclosed quote".to_string()));
+        assert_eq!(split_tokens("1.2.3.4 [10/Oct/2000"), Err("Unclosed bracket".to_string()));
+        assert!(extract(r#"1.2.3.4 "GET / HTTP/1.1 200 "-""#, false).is_err());
+    }
+}
@@ -1,10 +1,13 @@
 use visits::Visit;
+use hit::TimeDisplay;
+use latency::fmt_duration;
 use ncurses::{newwin, box_, mvwinsnstr, wrefresh};
 
 pub struct VisitDetailPanel {
     scry: i32,
     scrx: i32,
     visit: Option<Visit>,
+    time_display: TimeDisplay,
 }
 
 impl VisitDetailPanel {
@@ -13,6 +16,7 @@ impl VisitDetailPanel {
             scry: scry,
             scrx: scrx,
             visit: None,
+            time_display: TimeDisplay::Local,
         }
     }
 
@@ -20,13 +24,18 @@ impl VisitDetailPanel {
     fn _output_contents(&self, visit: &Visit) {
         let width = self.scrx / 2;
         let height = self.scry - 1;
+        let latency = match visit.latency() {
+            Some(stats) => stats.fmt_summary(),
+            None => "No response times".to_string(),
+        };
         let lines = [
             &visit.host[..],
-            &visit.fmt_time_range()[..],
+            &visit.fmt_time_range(self.time_display)[..],
             &visit.fmt_bytes()[..],
             &format!("Hits: {}", visit.hit_count)[..],
             &format!("4xx: {}", visit.hit_4xx_count)[..],
             &format!("5xx: {}", visit.hit_5xx_count)[..],
+            &latency[..],
             &visit.referer[..],
             &visit.agent[..],
         ];
@@ -39,7 +48,11 @@ impl VisitDetailPanel {
         let hits_overflow = visit.hits.len() > hits_height;
         let take_hits = if hits_overflow { hits_height - 1} else { hits_height };
         for (index, hit) in visit.hits.iter().take(take_hits).enumerate() {
-            let fmt = format!("{} {} {}", hit.fmt_time(), hit.status, hit.path);
+            let duration = hit.duration.map(fmt_duration).unwrap_or(String::new());
+            let fmt = format!(
+                "{} {} {} {}{} {} {}", hit.fmt_time(self.time_display), hit.status, hit.method,
+                hit.site, hit.path_with_query(), hit.protocol, duration
+            );
             mvwinsnstr(w, (index+hits_startline) as i32, 1, &fmt, width-2);
         }
         if hits_overflow {
@@ -67,6 +80,10 @@ impl VisitDetailPanel {
         self.visit = Some(visit.clone());
     }
 
+    pub fn set_time_display(&mut self, display: TimeDisplay) {
+        self.time_display = display;
+    }
+
     pub fn refresh(&self) {
         match self.visit {
             Some(ref visit) => self._output_contents(visit),
@@ -2,12 +2,22 @@ use std::cmp::Ordering;
 use std::collections::hash_map;
 use std::collections::hash_set::HashSet;
 use std::vec;
-use time::strftime;
 use number_prefix::{binary_prefix, Standalone, Prefixed};
-use hit::{Hit, is_path_resource};
+use hit::{Hit, TimeDisplay, is_path_resource, fmt_hour_minute};
+use latency::LatencyStats;
 
 pub type VisitID = u32;
 
+// A visit ends when its host hasn't hit us for that long, in seconds.
+pub const VISIT_TIMEOUT: i64 = 5 * 60;
+
+fn fmt_bytes(bytes: u64) -> String {
+    match binary_prefix(bytes as f64) {
+        Standalone(bytes) => format!("{:>3}B", bytes),
+        Prefixed(prefix, n) => format!("{:>3.0}{}B", n, prefix),
+    }
+}
+
 #[derive(Clone)]
 pub struct Visit {
     pub id: VisitID,
@@ -35,7 +45,7 @@ impl Visit {
             bytes: 0,
             first_hit_time: hit.time,
             last_hit_time: hit.time,
-            last_path: hit.path.clone(),
+            last_path: hit.site_path(),
             referer: hit.referer.clone(),
             agent: hit.agent.clone(),
             hits: Vec::new(),
@@ -46,17 +56,19 @@ impl Visit {
         self.hit_4xx_count > 0 || self.hit_5xx_count > 0
     }
 
-    pub fn fmt_time_range(&self) -> String {
-        let first_time_fmt = strftime("%H:%M", &self.first_hit_time).unwrap();
-        let last_time_fmt = strftime("%H:%M", &self.last_hit_time).unwrap();
+    pub fn fmt_time_range(&self, display: TimeDisplay) -> String {
+        let first_time_fmt = fmt_hour_minute(&self.first_hit_time, display);
+        let last_time_fmt = fmt_hour_minute(&self.last_hit_time, display);
         format!("{}-{}", first_time_fmt, last_time_fmt)
     }
 
     pub fn fmt_bytes(&self) -> String {
-        match binary_prefix(self.bytes as f32) {
-            Standalone(bytes) => format!("{:>3}B", bytes),
-            Prefixed(prefix, n) => format!("{:>3.0}{}B", n, prefix),
-        }
+        fmt_bytes(self.bytes as u64)
+    }
+
+    pub fn latency(&self) -> Option<LatencyStats> {
+        let mut durations: Vec<u64> = self.hits.iter().filter_map(|h| h.duration).collect();
+        LatencyStats::from_durations(&mut durations)
     }
 
     pub fn feed_hit(&mut self, hit: &Hit) {
@@ -74,12 +86,37 @@ impl Visit {
          * one.
          */
         if (!hit.is_resource()) || is_path_resource(&self.last_path) {
-            self.last_path = hit.path.clone();
+            self.last_path = hit.site_path();
         }
         self.hits.push(Box::new(hit.clone()));
     }
 }
 
+/// Totals for one of the sites we follow, over active visits.
+pub struct SiteStats {
+    pub name: String,
+    pub visit_count: u32,
+    pub hit_count: u32,
+    pub hit_problem_count: u32,
+    pub bytes: u64,
+}
+
+impl SiteStats {
+    fn new(name: &str) -> SiteStats {
+        SiteStats {
+            name: name.to_string(),
+            visit_count: 0,
+            hit_count: 0,
+            hit_problem_count: 0,
+            bytes: 0,
+        }
+    }
+
+    pub fn fmt_bytes(&self) -> String {
+        fmt_bytes(self.bytes)
+    }
+}
+
 type VisitHolder = hash_map::HashMap<VisitID, Box<Visit>>;
 type HostVisitMap = hash_map::HashMap<String, VisitID>;
 type StringVisitMap = hash_map::HashMap<String, Box<HashSet<VisitID>>>;
@@ -91,17 +128,78 @@ pub struct VisitStats {
     host_visit_map: HostVisitMap,
     path_visit_map: StringVisitMap,
     referer_visit_map: StringVisitMap,
+    // Whether paths in `path_visit_map` include their query string
+    path_with_query: bool,
 }
 
 impl VisitStats {
     pub fn new() -> VisitStats {
         VisitStats {
             visit_counter: 0,
-            last_seen_time:  ::time::now(),
+            last_seen_time:  ::time::now_utc(),
             visits: hash_map::HashMap::new(),
             host_visit_map: hash_map::HashMap::new(),
             path_visit_map: hash_map::HashMap::new(),
             referer_visit_map: hash_map::HashMap::new(),
+            path_with_query: false,
+        }
+    }
+
+    /// Forgets about all visits.
+    pub fn clear(&mut self) {
+        self.last_seen_time = ::time::now_utc();
+        self.visits.clear();
+        self.host_visit_map.clear();
+        self.path_visit_map.clear();
+        self.referer_visit_map.clear();
+    }
+
+    /// Returns the key under which `hit` is counted in Path mode.
+    ///
+    /// Paths are prefixed with their site name so that the same path on two sites is counted
+    /// separately.
+    ///
+    fn path_key(&self, hit: &Hit) -> String {
+        if self.path_with_query {
+            format!("{}{}", hit.site, hit.path_with_query())
+        } else {
+            hit.site_path()
+        }
+    }
+
+    fn add_to_path_map(&mut self, key: String, visitid: VisitID) {
+        match self.path_visit_map.entry(key) {
+            hash_map::Entry::Occupied(e) => {
+                let visits: &mut Box<HashSet<VisitID>> = e.into_mut();
+                visits.insert(visitid);
+            }
+            hash_map::Entry::Vacant(e) => {
+                let mut visits = Box::new(HashSet::new());
+                visits.insert(visitid);
+                e.insert(visits);
+            }
+        };
+    }
+
+    pub fn is_path_with_query(&self) -> bool {
+        self.path_with_query
+    }
+
+    /// Sets whether paths are grouped along with their query string, and regroups current visits.
+    pub fn set_path_with_query(&mut self, path_with_query: bool) {
+        if path_with_query == self.path_with_query {
+            return;
+        }
+        self.path_with_query = path_with_query;
+        let mut keys: Vec<(String, VisitID)> = Vec::new();
+        for visit in self.visits.values() {
+            for hit in visit.hits.iter() {
+                keys.push((self.path_key(hit), visit.id));
+            }
+        }
+        self.path_visit_map.clear();
+        for (key, visitid) in keys.into_iter() {
+            self.add_to_path_map(key, visitid);
         }
     }
 
@@ -120,21 +218,11 @@ impl VisitStats {
                 visitid
             }
         };
+        let key = self.path_key(hit);
+        self.add_to_path_map(key, visitid);
         let visit: &mut Box<Visit> = self.visits.get_mut(&visitid).unwrap();
         visit.feed_hit(hit);
         self.last_seen_time = hit.time;
-        let key = &hit.path;
-        match self.path_visit_map.entry(key.clone()) {
-            hash_map::Entry::Occupied(e) => {
-                let visits: &mut Box<HashSet<VisitID>> = e.into_mut();
-                visits.insert(visitid);
-            }
-            hash_map::Entry::Vacant(e) => {
-                let mut visits = Box::new(HashSet::new());
-                visits.insert(visitid);
-                e.insert(visits);
-            }
-        };
         // Referer counting only makes sense for the first hit of the visit
         if visit.hit_count == 1 {
             let key = &visit.referer;
@@ -156,7 +244,7 @@ impl VisitStats {
         let mut toremove: Vec<VisitID> = Vec::new();
         let last_seen_ts = self.last_seen_time.to_timespec();
         for (visitid, visit) in self.visits.iter() {
-            if last_seen_ts.sec - visit.last_hit_time.to_timespec().sec > 5 * 60 {
+            if last_seen_ts.sec - visit.last_hit_time.to_timespec().sec > VISIT_TIMEOUT {
                 toremove.push(*visitid);
                 self.host_visit_map.remove(&visit.host);
                 let empty = {
@@ -218,6 +306,56 @@ impl VisitStats {
         sorted_path_chunks.into_iter()
     }
 
+    /// Returns latency stats by path, slowest first.
+    ///
+    /// Paths are ranked by their tail latency (p99, then p95). Paths without any timed hit are
+    /// left out.
+    ///
+    pub fn iter_sorted_path_latencies(&self) -> vec::IntoIter<(String, LatencyStats)> {
+        let mut path_durations: hash_map::HashMap<String, Vec<u64>> = hash_map::HashMap::new();
+        for visit in self.visits.values() {
+            for hit in visit.hits.iter() {
+                if let Some(duration) = hit.duration {
+                    path_durations.entry(self.path_key(hit)).or_insert(Vec::new()).push(duration);
+                }
+            }
+        }
+        let mut sorted_latencies: Vec<(String, LatencyStats)> = path_durations.into_iter().filter_map(
+            |(path, mut durations)| LatencyStats::from_durations(&mut durations).map(|stats| (path, stats))
+        ).collect();
+        sorted_latencies.sort_by(
+            |a, b| (a.1.p99, a.1.p95).cmp(&(b.1.p99, b.1.p95)).reverse()
+        );
+        sorted_latencies.into_iter()
+    }
+
+    /// Returns per-site totals, busiest site first.
+    ///
+    /// A visit that has hits on several sites is counted once for each of them.
+    ///
+    pub fn iter_sorted_site_stats(&self) -> vec::IntoIter<SiteStats> {
+        let mut site_map: hash_map::HashMap<&str, SiteStats> = hash_map::HashMap::new();
+        for visit in self.visits.values() {
+            let mut visited_sites: HashSet<&str> = HashSet::new();
+            for hit in visit.hits.iter() {
+                let stats = site_map.entry(&hit.site[..]).or_insert_with(|| SiteStats::new(&hit.site));
+                stats.hit_count += 1;
+                stats.bytes += hit.bytes as u64;
+                if hit.is_4xx() || hit.is_5xx() {
+                    stats.hit_problem_count += 1;
+                }
+                if visited_sites.insert(&hit.site[..]) {
+                    stats.visit_count += 1;
+                }
+            }
+        }
+        let mut sorted_sites: Vec<SiteStats> = site_map.into_iter().map(|(_, stats)| stats).collect();
+        sorted_sites.sort_by(
+            |a, b| a.hit_count.cmp(&b.hit_count).reverse()
+        );
+        sorted_sites.into_iter()
+    }
+
     pub fn iter_sorted_referer_chunks(&self) -> vec::IntoIter<(&str, u32)> {
         let mut sorted_referer_chunks: Vec<(&str, u32)> = self.referer_visit_map.iter().map(
             |(key, value)| (&key[..], value.len() as u32)
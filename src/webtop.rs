@@ -3,152 +3,466 @@ extern crate time;
 extern crate ncurses;
 extern crate libc;
 extern crate number_prefix;
+extern crate getopts;
+extern crate rustc_serialize;
 
-use std::io::prelude::*;
-use std::io;
 use std::fs;
 use std::ffi::CString;
 use std::path::Path;
-use std::sync::mpsc::{Sender, Receiver};
-use std::sync::mpsc;
+use std::mem;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Receiver;
 use std::thread;
-use time::precise_time_s;
+use std::process;
+use std::cmp::{min, max};
+use time::{precise_time_s, get_time, strptime, Timespec};
+use getopts::Options;
 use ncurses::{
     initscr, getch, raw, keypad, nodelay, noecho, stdscr, endwin, newterm, set_term, curs_set,
     CURSOR_VISIBILITY, setlocale, LcCategory
 };
 use ncurses::ll;
 use visits::*;
-use parse::Parser;
+use hit::TimeDisplay;
+use parse::{Parser, SharedParser, to_utc};
+use formats::{detect_format, format_names, parser_for};
+use tail::Backfill;
+use input::{InputMsg, input_channel, spawn_stdin_reader, spawn_file_follower, spawn_dir_follower, site_name_from_path};
 use screen::Screen;
+use syslog::{SyslogAddr, spawn_syslog_listener};
+use exec::CommandSource;
+use replay::{Replay, ReplaySpeed, SEEK_SECONDS};
 
 mod hit;
 mod visits;
+mod tail;
+#[cfg(target_os = "linux")]
+mod inotify;
+#[cfg(not(target_os = "linux"))]
+#[path = "no_inotify.rs"]
+mod inotify;
+mod input;
+mod line_buffer;
+mod replay;
+mod syslog;
+mod exec;
+mod latency;
 mod parse;
+mod json_parse;
+mod formats;
+mod token_parse;
 mod screen;
 mod help_panel;
 mod visit_detail_panel;
+mod rejected_lines_panel;
 
 const HELP_KEY: i32 = '?' as i32;
 const QUIT_KEY: i32 = 'q' as i32;
 const HOST_KEY: i32 = 'h' as i32;
 const PATH_KEY: i32 = 'p' as i32;
 const REFERER_KEY: i32 = 'r' as i32;
+const SITE_KEY: i32 = 's' as i32;
+const SLOW_KEY: i32 = 'l' as i32;
 const DETAIL_KEY: i32 = 'd' as i32;
+const TIMEZONE_KEY: i32 = 'z' as i32;
+const QUERY_KEY: i32 = 'a' as i32;
+const REJECTED_KEY: i32 = 'e' as i32;
 const UP_KEY: i32 = 259;
 const DOWN_KEY: i32 = 258;
+const PAUSE_KEY: i32 = ' ' as i32;
+const STEP_KEY: i32 = 'n' as i32;
+const SEEK_BACK_KEY: i32 = '[' as i32;
+const SEEK_FORWARD_KEY: i32 = ']' as i32;
+
+// How many lines we gather before trying to detect the log format
+const DETECTION_SAMPLE_SIZE: usize = 20;
+
+// How many rejected lines we keep around for the rejected lines panel
+const MAX_REJECTED_LINES: usize = 100;
+
+// When new input comes in, we refresh right away, but not more often than this, in seconds.
+const MIN_REFRESH_INTERVAL: f64 = 0.2;
+
+// How much data, in bytes, we take from our input threads before feeding it. Past that, they wait.
+const MAX_PENDING_SIZE: usize = 16 * 1024 * 1024;
+
+// How long, in seconds, we feed data before getting back to the keyboard and the screen
+const FEED_TIME_BUDGET: f64 = 0.05;
+
+// We tell how far behind our input we are when it's more than this, in seconds.
+const MIN_SHOWN_LAG: f64 = 1.0;
+
+// When sampling, we skip one more line out of each group for each of these seconds we're behind.
+const SAMPLING_LAG_STEP: f64 = 1.0;
+const MAX_SAMPLE_RATE: usize = 100;
+
+// How long, in seconds, we keep notes about our input in the status line
+const STATUS_NOTE_DURATION: f64 = 10.0;
+
+// Wide enough for a full IPv6 address
+const MAX_HOST_WIDTH: usize = 39;
 
 #[derive(PartialEq, Copy, Clone)]
 enum ProgramMode {
     Host,
     URLPath,
     Referer,
+    Slow,
+    Site,
 }
 
-#[derive(Copy, Clone)]
 enum PathOrStdin<'a> {
     Path(&'a Path),
-    Stdin(&'a Receiver<String>),
+    // Directory, and the pattern its file names have to match, if any
+    Dir(&'a Path, Option<String>),
+    Stdin,
+    Syslog(SyslogAddr),
+}
+
+fn remember_rejected_line(rejected_lines: &mut VecDeque<(String, String)>, reason: String, line: String) {
+    rejected_lines.push_back((reason, line));
+    if rejected_lines.len() > MAX_REJECTED_LINES {
+        rejected_lines.pop_front();
+    }
+}
+
+/// Splits a "SITE=SOURCE" argument into its site name and its source.
+///
+/// An argument without a site name, or that is the name of an existing file, is all source.
+///
+fn split_site_arg(arg: &str) -> (Option<&str>, &str) {
+    if fs::metadata(arg).is_ok() {
+        return (None, arg);
+    }
+    match arg.find('=') {
+        Some(index) if index > 0 && !arg[..index].contains(|c: char| c == '/' || c.is_whitespace()) => {
+            (Some(&arg[..index]), &arg[index+1..])
+        },
+        _ => (None, arg),
+    }
 }
 
-struct WholeThing<'a> {
-    inpath: PathOrStdin<'a>,
-    parser: Parser,
+/// Parses the argument of `--since`.
+///
+/// That's either a local time, "YYYY-MM-DD HH:MM[:SS]", or a duration ago, such as "30m", in
+/// seconds (s), minutes (m), hours (h) or days (d).
+///
+fn parse_since(value: &str) -> Result<Timespec, String> {
+    let value = value.trim();
+    let multiplier = match value.chars().last() {
+        Some('s') => Some(1),
+        Some('m') => Some(60),
+        Some('h') => Some(60 * 60),
+        Some('d') => Some(24 * 60 * 60),
+        _ => None,
+    };
+    if let Some(multiplier) = multiplier {
+        if let Ok(count) = value[..value.len()-1].parse::<i64>() {
+            return Ok(Timespec::new(get_time().sec - count * multiplier, 0));
+        }
+    }
+    for fmt in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"].iter() {
+        if let Ok(tm) = strptime(value, fmt) {
+            return Ok(to_utc(tm, None).to_timespec());
+        }
+    }
+    Err(format!(
+        "Invalid time for --since: {}. Use \"YYYY-MM-DD HH:MM[:SS]\" or a duration such as \"30m\".",
+        value
+    ))
+}
+
+struct WholeThing {
+    input: Receiver<InputMsg>,
+    // Lines our input threads couldn't send us because we were behind
+    dropped_lines: Arc<AtomicUsize>,
+    // (site, data, when it was read) we've received from `input` and not fed yet, oldest first
+    pending: VecDeque<(String, String, f64)>,
+    // Size of the data in `pending`, in bytes
+    pending_size: usize,
+    // How much data we've fed since the last refresh, in bytes
+    read_size: usize,
+    // How long, in seconds, the data we're feeding waited before we got to it
+    lag: f64,
+    // Whether we skip lines to keep up when we're behind, and we keep one line out of how many
+    sampling: bool,
+    sample_rate: usize,
+    sample_counter: usize,
+    skipped_lines: usize,
+    // `None` until we've detected the log format
+    parser: Option<SharedParser>,
+    // Parsers for sites whose logs don't have the same format as the others, by site name
+    site_parsers: HashMap<String, SharedParser>,
+    format_name: String,
+    // (site, line) we keep aside until we know the log format
+    detection_sample: Vec<(String, String)>,
+    rejected_count: u32,
+    // Lines that had invalid bytes, which we replaced
+    invalid_line_count: usize,
+    // (reason, raw line), most recent last
+    rejected_lines: VecDeque<(String, String)>,
     screen: Screen,
-    last_size: i64,
+    // Message about something that happened to our input, and until when we show it
+    status_note: Option<(String, f64)>,
     visit_stats: VisitStats,
     selected_visitid: VisitID,
     mode: ProgramMode,
+    time_display: TimeDisplay,
+    // When replaying logs, hits go through this rather than straight to `visit_stats`.
+    replay: Option<Replay>,
 }
 
-impl<'a> WholeThing<'a> {
-    fn new(inpath: PathOrStdin) -> WholeThing {
+impl WholeThing {
+    fn new(
+        input: Receiver<InputMsg>, dropped_lines: Arc<AtomicUsize>, parser: Option<SharedParser>, format_name: &str,
+        time_display: TimeDisplay
+    ) -> WholeThing {
+        let mut screen = Screen::new();
+        screen.set_time_display(time_display);
         WholeThing {
-            inpath: inpath,
-            parser: Parser::new(),
-            screen: Screen::new(),
-            last_size: 0,
+            input: input,
+            dropped_lines: dropped_lines,
+            pending: VecDeque::new(),
+            pending_size: 0,
+            read_size: 0,
+            lag: 0.0,
+            sampling: false,
+            sample_rate: 1,
+            sample_counter: 0,
+            skipped_lines: 0,
+            parser: parser,
+            site_parsers: HashMap::new(),
+            format_name: format_name.to_string(),
+            detection_sample: Vec::new(),
+            rejected_count: 0,
+            invalid_line_count: 0,
+            rejected_lines: VecDeque::new(),
+            screen: screen,
+            status_note: None,
             visit_stats: VisitStats::new(),
             selected_visitid: 0,
             mode: ProgramMode::Host,
+            time_display: time_display,
+            replay: None,
         }
     }
 
-    fn refresh_visit_stats(&mut self) {
-        let contents = match self.inpath {
-            PathOrStdin::Path(filepath) => {
-                let fsize = fs::metadata(filepath).ok().expect("can't stat").len() as i64;
-                if fsize < self.last_size {
-                    let msg = "Something weird is happening with the target file, skipping this round.";
-                    self.screen.printstatus(&msg[..]);
-                    return;
+    /// Takes what our input threads have sent us so far, unless we already have plenty to feed.
+    fn poll_input(&mut self) {
+        while self.pending_size < MAX_PENDING_SIZE {
+            match self.input.try_recv() {
+                Ok(InputMsg::Data(site, data, read_at)) => {
+                    self.pending_size += data.len();
+                    self.pending.push_back((site, data, read_at));
+                },
+                Ok(InputMsg::InvalidBytes(count)) => self.invalid_line_count += count,
+                Ok(InputMsg::Rejected(reason, line)) => {
+                    self.rejected_count += 1;
+                    remember_rejected_line(&mut self.rejected_lines, reason, line);
+                },
+                Ok(InputMsg::Note(note, lasting)) => {
+                    let until = if lasting { f64::INFINITY } else { precise_time_s() + STATUS_NOTE_DURATION };
+                    self.status_note = Some((note, until));
+                },
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// Returns out of how many lines we keep one, given how far behind we are.
+    fn sample_rate_for(&self, lag: f64) -> usize {
+        if !self.sampling {
+            return 1;
+        }
+        min(MAX_SAMPLE_RATE, 1 + (lag / SAMPLING_LAG_STEP) as usize)
+    }
+
+    /// Feeds pending data, oldest first, for as long as our time budget allows.
+    ///
+    /// Until we know the log format, lines go to the detection sample, and we stop once it's
+    /// full.
+    ///
+    fn feed_pending(&mut self) {
+        let started_at = precise_time_s();
+        let mut lag = 0.0;
+        while precise_time_s() - started_at < FEED_TIME_BUDGET {
+            if self.parser.is_none() && self.detection_sample.len() >= DETECTION_SAMPLE_SIZE {
+                break;
+            }
+            let (site, data, read_at) = match self.pending.pop_front() {
+                Some(pending) => pending,
+                None => break,
+            };
+            self.pending_size -= data.len();
+            self.read_size += data.len();
+            lag = precise_time_s() - read_at;
+            let lines = data.split('\n').filter(|l| !l.trim().is_empty());
+            if self.parser_for_site(&site).is_none() {
+                self.detection_sample.extend(lines.map(|l| (site.clone(), l.to_string())));
+                continue;
+            }
+            self.sample_rate = self.sample_rate_for(lag);
+            let mut kept = Vec::new();
+            for line in lines {
+                self.sample_counter += 1;
+                if self.sample_counter % self.sample_rate == 0 {
+                    kept.push(line);
                 }
-                let read_size: i64 = if self.last_size > 0 { fsize - self.last_size } else { 90000 };
-                self.last_size = fsize;
-                let mut fp = match fs::File::open(filepath) {
-                    Ok(fp) => fp,
-                    Err(e) => {
-                        let msg = format!(
-                            "Had troube reading {}! Error: {}",
-                            filepath.display(), e,
-                        );
-                        self.screen.printstatus(&msg[..]);
-                        return;
-                    },
-                };
-                let _ = fp.seek(io::SeekFrom::End(-read_size));
-                let mut res = String::new();
-                fp.read_to_string(&mut res).unwrap();
-                res
-            },
-            PathOrStdin::Stdin(rx) => {
-                let mut res = String::new();
-                loop {
-                    match rx.try_recv() {
-                        Ok(msg) => {
-                            res.push_str(&msg[..]);
-                        },
-                        Err(_) => { break; }
-                    }
+                else {
+                    self.skipped_lines += 1;
                 }
-                res
-            },
+            }
+            self.feed_lines(&site, kept.into_iter());
+        }
+        // What's still waiting matters more than what we just fed.
+        self.lag = match self.pending.front() {
+            Some(pending) => precise_time_s() - pending.2,
+            None => lag,
         };
-        let read_size = contents.len();
-        for line in contents.split('\n') {
-            let hit = match self.parser.parse_line(line) {
-                Some(hit) => hit,
-                None => continue
-            };
-            self.visit_stats.feed_hit(&hit);
+        if self.pending.is_empty() && lag == 0.0 {
+            self.sample_rate = 1;
+        }
+    }
+
+    fn refresh_visit_stats(&mut self) {
+        let read_size = mem::replace(&mut self.read_size, 0);
+        if self.parser.is_none() {
+            // If input is slow, we make do with what we have.
+            let enough = self.detection_sample.len() >= DETECTION_SAMPLE_SIZE || read_size == 0;
+            if enough && !self.detection_sample.is_empty() {
+                self.detect_format();
+            }
         }
         self.visit_stats.purge_visits();
         match self.mode {
             ProgramMode::URLPath => self.output_path_mode(),
             ProgramMode::Referer => self.output_referer_mode(),
+            ProgramMode::Slow => self.output_slow_mode(),
+            ProgramMode::Site => self.output_site_mode(),
             ProgramMode::Host => self.output_host_mode(),
         };
         let mode_str = match self.mode {
             ProgramMode::Host => "Host",
-            ProgramMode::URLPath => "Path",
+            ProgramMode::URLPath => {
+                if self.visit_stats.is_path_with_query() { "Path+query" } else { "Path" }
+            },
             ProgramMode::Referer => "Referer",
+            ProgramMode::Slow => "Slow",
+            ProgramMode::Site => "Site",
         };
-        let msg = format!(
-            "{} active visits. Last read: {} bytes. {} mode. Hit '?' for help.",
-            self.visit_stats.visit_count(), read_size, mode_str
+        // When replaying, where we are in time matters more than how much we read.
+        let mut progress = match self.replay {
+            Some(ref replay) => replay.fmt_status(self.time_display),
+            None => format!("Last read: {} bytes.", read_size),
+        };
+        if self.lag >= MIN_SHOWN_LAG {
+            progress = format!("{} Lag: {:.0}s.", progress, self.lag);
+        }
+        let dropped_lines = self.dropped_lines.load(Ordering::Relaxed) + self.skipped_lines;
+        if dropped_lines > 0 {
+            progress = format!("{} {} dropped lines.", progress, dropped_lines);
+        }
+        if self.sample_rate > 1 {
+            progress = format!("{} Sampling 1 line in {}.", progress, self.sample_rate);
+        }
+        if self.invalid_line_count > 0 {
+            progress = format!("{} {} lines with invalid bytes.", progress, self.invalid_line_count);
+        }
+        let mut msg = format!(
+            "{} active visits. {} {} rejected lines. {} mode. Format: {}. Hit '?' for help.",
+            self.visit_stats.visit_count(), progress, self.rejected_count, mode_str, self.format_name
         );
+        let note_expired = match self.status_note {
+            Some((ref note, until)) => {
+                msg = format!("{} {}", note, msg);
+                precise_time_s() > until
+            },
+            None => false,
+        };
+        if note_expired {
+            self.status_note = None;
+        }
+        if self.screen.is_showing_rejected_lines() {
+            self.screen.set_rejected_lines(self.rejected_count, &self.rejected_lines);
+        }
         self.screen.printstatus(&msg[..]);
         self.screen.refresh();
     }
 
+    /// Returns the parser for the logs of `site`, if we know their format yet.
+    fn parser_for_site(&self, site: &str) -> Option<SharedParser> {
+        match self.site_parsers.get(site) {
+            Some(parser) => Some(parser.clone()),
+            None => self.parser.clone(),
+        }
+    }
+
+    fn feed_lines<'b, I: Iterator<Item=&'b str>>(&mut self, site: &str, lines: I) {
+        let parser = match self.parser_for_site(site) {
+            Some(parser) => parser,
+            None => return,
+        };
+        for line in lines {
+            if line.trim().is_empty() || parser.is_comment(line) {
+                continue;
+            }
+            let mut hit = match parser.try_parse_line(line) {
+                Ok(hit) => hit,
+                Err(reason) => {
+                    self.rejected_count += 1;
+                    remember_rejected_line(&mut self.rejected_lines, reason, line.to_string());
+                    continue;
+                },
+            };
+            hit.site = site.to_string();
+            match self.replay {
+                Some(ref mut replay) => replay.add(hit, &mut self.visit_stats),
+                None => self.visit_stats.feed_hit(&hit),
+            }
+        }
+    }
+
+    /// Picks the log format that best matches our sample and feeds the sample through it.
+    ///
+    /// If nothing matches, the sample is dropped and we try again with the next lines.
+    ///
+    fn detect_format(&mut self) {
+        let sample = mem::replace(&mut self.detection_sample, Vec::new());
+        let lines: Vec<String> = sample.iter().map(|s| s.1.clone()).collect();
+        match detect_format(&lines) {
+            Some((name, parser)) => {
+                self.format_name = format!("{} (detected)", name);
+                self.parser = Some(parser);
+                for (site, line) in sample.into_iter() {
+                    self.feed_lines(&site, Some(&line[..]).into_iter());
+                }
+            },
+            None => {
+                self.format_name = "unknown".to_string();
+                for line in lines.into_iter() {
+                    self.rejected_count += 1;
+                    let reason = "No known log format matches".to_string();
+                    remember_rejected_line(&mut self.rejected_lines, reason, line);
+                }
+            },
+        }
+    }
+
     fn output_host_mode(&mut self) {
         self.screen.erase();
-        for (index, visit) in self.visit_stats.iter_sorted_visits().take(self.screen.maxlines() as usize).enumerate() {
+        let visits: Vec<&Visit> = self.visit_stats.iter_sorted_visits().take(self.screen.maxlines() as usize).collect();
+        // IPv4 addresses always fit in 15 columns, but IPv6 addresses and hostnames can be wider.
+        let longest_host = visits.iter().map(|v| v.host.chars().count()).max().unwrap_or(0);
+        let host_width = min(max(longest_host, 15), MAX_HOST_WIDTH);
+        for (index, visit) in visits.into_iter().enumerate() {
             let problem_marker = if visit.has_problems() { "!" } else { " " };
+            let host: String = visit.host.chars().take(host_width).collect();
             let visit_fmt = format!(
-                "{}{:>4} | {:<15} | {} | {:<6} | {} | {}",
-                problem_marker, visit.hit_count, visit.host, visit.fmt_time_range(),
-                visit.fmt_bytes(), visit.last_path, visit.referer
+                "{}{:>4} | {:<width$} | {} | {:<6} | {} | {}",
+                problem_marker, visit.hit_count, host, visit.fmt_time_range(self.time_display),
+                visit.fmt_bytes(), visit.last_path, visit.referer, width=host_width
             );
             self.screen.printline(index as u32, &visit_fmt[..]);
             if (index as u32) == self.screen.selected_index {
@@ -172,6 +486,32 @@ impl<'a> WholeThing<'a> {
         self.screen.adjust_selection();
     }
 
+    fn output_slow_mode(&mut self) {
+        self.screen.erase();
+        for (index, pair) in self.visit_stats.iter_sorted_path_latencies().take(self.screen.maxlines() as usize).enumerate() {
+            let (path, stats) = pair;
+            let path_fmt = format!(
+                "{:>4} | {} | {}",
+                stats.count, stats.fmt_summary(), path,
+            );
+            self.screen.printline(index as u32, &path_fmt[..]);
+        }
+        self.screen.adjust_selection();
+    }
+
+    fn output_site_mode(&mut self) {
+        self.screen.erase();
+        for (index, stats) in self.visit_stats.iter_sorted_site_stats().take(self.screen.maxlines() as usize).enumerate() {
+            let name = if stats.name.is_empty() { "-" } else { &stats.name[..] };
+            let site_fmt = format!(
+                "{:>4} | {:>6} hits | {:>5} 4xx/5xx | {:<6} | {}",
+                stats.visit_count, stats.hit_count, stats.hit_problem_count, stats.fmt_bytes(), name,
+            );
+            self.screen.printline(index as u32, &site_fmt[..]);
+        }
+        self.screen.adjust_selection();
+    }
+
     fn output_referer_mode(&mut self) {
         self.screen.erase();
         for (index, pair) in self.visit_stats.iter_sorted_referer_chunks().take(self.screen.maxlines() as usize).enumerate() {
@@ -194,14 +534,49 @@ impl<'a> WholeThing<'a> {
         };
     }
 
+    fn toggle_time_display(&mut self) {
+        self.time_display = match self.time_display {
+            TimeDisplay::Local => TimeDisplay::UTC,
+            TimeDisplay::UTC => TimeDisplay::Local,
+        };
+        self.screen.set_time_display(self.time_display);
+    }
+
+    fn control_replay(&mut self, key: i32) {
+        let replay = match self.replay {
+            Some(ref mut replay) => replay,
+            None => return,
+        };
+        match key {
+            PAUSE_KEY => replay.toggle_pause(),
+            STEP_KEY => replay.step(&mut self.visit_stats),
+            SEEK_BACK_KEY => replay.seek(-SEEK_SECONDS, &mut self.visit_stats),
+            SEEK_FORWARD_KEY => replay.seek(SEEK_SECONDS, &mut self.visit_stats),
+            _ => (),
+        }
+    }
+
     fn mainloop(&mut self) -> i32 {
         let mut last_refresh_time: f64 = 0.0;
         loop {
-            if precise_time_s() - last_refresh_time > 1.0 {
+            self.poll_input();
+            self.feed_pending();
+            if let Some(ref mut replay) = self.replay {
+                replay.tick(&mut self.visit_stats);
+            }
+            let has_input = self.read_size > 0 || !self.pending.is_empty();
+            let elapsed = precise_time_s() - last_refresh_time;
+            // A replay's clock moves on even when there's no input.
+            let eager = has_input || self.replay.is_some();
+            if elapsed > 1.0 || (eager && elapsed > MIN_REFRESH_INTERVAL) {
                 self.refresh_visit_stats();
                 last_refresh_time = precise_time_s();
             }
-            thread::sleep_ms(50);
+            // When we're behind, we get back to feeding right away.
+            let catching_up = self.replay.as_ref().map_or(false, |replay| replay.is_catching_up());
+            if self.pending.is_empty() && !catching_up {
+                thread::sleep_ms(50);
+            }
             let input = getch();
             if input >= 0 {
                 self.mode = match input {
@@ -211,10 +586,23 @@ impl<'a> WholeThing<'a> {
                     PATH_KEY => ProgramMode::URLPath,
                     HOST_KEY => ProgramMode::Host,
                     REFERER_KEY => ProgramMode::Referer,
+                    SLOW_KEY => ProgramMode::Slow,
+                    SITE_KEY => ProgramMode::Site,
                     UP_KEY => { self.screen.up(); self.mode },
                     DOWN_KEY => { self.screen.down(); self.mode },
                     HELP_KEY => { self.screen.toggle_help(); self.mode },
                     DETAIL_KEY => { self.show_selected_visit(); self.mode },
+                    TIMEZONE_KEY => { self.toggle_time_display(); self.mode },
+                    PAUSE_KEY | STEP_KEY | SEEK_BACK_KEY | SEEK_FORWARD_KEY => {
+                        self.control_replay(input);
+                        self.mode
+                    },
+                    REJECTED_KEY => { self.screen.toggle_rejected_lines(); self.mode },
+                    QUERY_KEY => {
+                        let with_query = self.visit_stats.is_path_with_query();
+                        self.visit_stats.set_path_with_query(!with_query);
+                        self.mode
+                    },
                     _ => self.mode,
                 };
                 last_refresh_time = 0.0;
@@ -227,37 +615,275 @@ impl<'a> WholeThing<'a> {
 fn main()
 {
     setlocale(LcCategory::all, "en_US.UTF-8");
-    let mut args = ::std::env::args();
-    if args.len() < 2 {
+    let args: Vec<String> = ::std::env::args().collect();
+    let mut opts = Options::new();
+    opts.optopt(
+        "f", "format",
+        &format!(
+            "Apache LogFormat string or the name of a known format ({}). Detected automatically \
+            by default.",
+            format_names().join(", ")
+        ),
+        "FORMAT"
+    );
+    opts.optmulti(
+        "", "site-format",
+        "Log format of SITE, when it differs from the others. FORMAT is given as for --format. \
+        Can be given several times.",
+        "SITE=FORMAT"
+    );
+    opts.optopt(
+        "n", "nginx-format",
+        "nginx log_format directive, copied from nginx.conf, or \"combined\".",
+        "LOG_FORMAT"
+    );
+    opts.optflag("j", "json", "Read logs with one JSON object per line");
+    opts.optopt(
+        "", "json-map",
+        "Keys to read hit fields from in JSON logs. Implies --json. \
+        Example: host=request.remote_ip,time=ts,status=status",
+        "MAPPING"
+    );
+    opts.optflag("", "path-query", "Group hits by path and query string in Path mode");
+    opts.optflag("u", "utc", "Display times in UTC rather than in local time");
+    opts.optflag("", "from-start", "Read log files from their start rather than only their end");
+    opts.optopt("", "last-lines", "Start with the last N lines of log files", "N");
+    opts.optopt(
+        "", "since",
+        "Start with what was logged since TIME, either a local time (\"YYYY-MM-DD HH:MM[:SS]\") or \
+        a duration ago (\"30m\", in s, m, h or d)",
+        "TIME"
+    );
+    opts.optflagopt(
+        "", "replay",
+        "Replay logs from their start, paced by their own times, at SPEED: 1x (default), 10x, \
+        60x, any other factor, or max",
+        "SPEED"
+    );
+    opts.optmulti(
+        "", "exec",
+        "Run COMMAND and read logs from its output, restarting it when it ends. Can be given \
+        several times. Example: --exec \"ssh www tail -F /var/log/nginx/access.log\"",
+        "[SITE=]COMMAND"
+    );
+    opts.optflag(
+        "", "sample",
+        "When logs come in faster than we can keep up with, skip part of them to stay close to \
+        real time",
+    );
+    opts.optflag("h", "help", "Print this help");
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    if matches.opt_present("h") {
+        let brief = format!(
+            "Usage: {} [options] [SITE=]FILE|DIR|GLOB|ADDRESS...\n\n\
+            With several files, each one is a site, named after its file unless SITE is given.\n\
+            Log files in DIR, or matching GLOB, are followed as they come and go, each as a site.\n\
+            ADDRESS is where to listen for syslog messages: udp://HOST:PORT, tcp://HOST:PORT or\n\
+            unix:///PATH. Messages go to the site named after their tag, unless SITE is given.",
+            args[0]
+        );
+        print!("{}", opts.usage(&brief));
+        return;
+    }
+    let commands = matches.opt_strs("exec");
+    if matches.free.is_empty() && commands.is_empty() {
         println!("You need to specify a file to watch.");
         return;
     }
-    let _ = args.next();
-    let inpath = &args.next().unwrap()[..];
-    let filepath = &Path::new(inpath);
-    let (stdin_tx, stdin_rx): (Sender<String>, Receiver<String>) = mpsc::channel();
-    let (stdin_stopped_tx, stdin_stopped_rx): (Sender<bool>, Receiver<bool>) = mpsc::channel();
-    let path = match inpath {
-        "-" => {
-            thread::spawn(move || {
-                let stdin = io::stdin();
-                for line in stdin.lock().lines() {
-                    let mut topush = line.unwrap();
-                    topush.push_str(&"\n");
-                    stdin_tx.send(topush).unwrap();
-                }
-                stdin_stopped_tx.send(true).unwrap();
-            });
-            PathOrStdin::Stdin(&stdin_rx)
+    let json = matches.opt_present("j") || matches.opt_present("json-map");
+    let (parser, format_name) = match (matches.opt_str("f"), matches.opt_str("n"), json) {
+        (Some(format), None, false) => {
+            match parser_for(&format) {
+                Ok((parser, name)) => (Ok(Some(parser)), name),
+                Err(e) => (Err(e), String::new()),
+            }
+        },
+        (None, Some(directive), false) => {
+            let parser = Parser::from_nginx_format(&directive).map(|p| Some(Arc::new(p) as SharedParser));
+            (parser, "nginx".to_string())
         },
+        (None, None, true) => {
+            let mapping = matches.opt_str("json-map");
+            let parser = Parser::from_json_mapping(mapping.as_ref().map(|s| &s[..]));
+            (parser.map(|p| Some(Arc::new(p) as SharedParser)), "json".to_string())
+        },
+        // No format given, we'll detect it
+        (None, None, false) => (Ok(None), "detecting...".to_string()),
         _ => {
-            if fs::metadata(filepath).is_err() {
-                println!("{} doesn't exist! aborting.", filepath.display());
+            println!("--format, --nginx-format and --json can't be used together.");
+            return;
+        },
+    };
+    let parser = match parser {
+        Ok(parser) => parser,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let mut site_parsers: HashMap<String, SharedParser> = HashMap::new();
+    for arg in matches.opt_strs("site-format").iter() {
+        let (site, format) = match arg.find('=') {
+            Some(index) => (&arg[..index], &arg[index+1..]),
+            None => {
+                println!("Invalid --site-format: {}. Use SITE=FORMAT.", arg);
+                return;
+            }
+        };
+        match parser_for(format) {
+            Ok((parser, _)) => { site_parsers.insert(site.to_string(), parser); },
+            Err(e) => {
+                println!("{}", e);
                 return;
             }
-            PathOrStdin::Path(filepath)
+        }
+    }
+    let replay = if matches.opt_present("replay") {
+        let speed = matches.opt_str("replay").unwrap_or("1x".to_string());
+        match ReplaySpeed::from_name(&speed) {
+            Some(speed) => Some(Replay::new(speed)),
+            None => {
+                println!("Invalid replay speed: {}", speed);
+                return;
+            }
+        }
+    } else {
+        None
+    };
+    let backfill = match (matches.opt_present("from-start"), matches.opt_str("last-lines"), matches.opt_str("since")) {
+        // A replay starts from the start, unless told otherwise.
+        (false, None, None) if replay.is_some() => Backfill::Start,
+        (false, None, None) => Backfill::default(),
+        (true, None, None) => Backfill::Start,
+        (false, Some(count), None) => {
+            match count.parse() {
+                Ok(count) => Backfill::Lines(count),
+                Err(_) => {
+                    println!("Invalid line count for --last-lines: {}", count);
+                    return;
+                }
+            }
+        },
+        (false, None, Some(since)) => {
+            match parse_since(&since) {
+                Ok(since) => Backfill::Since(since, parser.clone()),
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            }
+        },
+        _ => {
+            println!("--from-start, --last-lines and --since can't be used together.");
+            return;
         },
     };
+    let several_sources = matches.free.len() + commands.len() > 1;
+    let mut sources: Vec<(String, PathOrStdin)> = Vec::new();
+    for arg in matches.free.iter() {
+        let (site, inpath) = split_site_arg(arg);
+        let source = match inpath {
+            "-" => PathOrStdin::Stdin,
+            _ if inpath.contains("://") => {
+                match SyslogAddr::parse(inpath) {
+                    Some(addr) => PathOrStdin::Syslog(addr),
+                    None => {
+                        println!("Unsupported address: {}. Use udp://, tcp:// or unix://.", inpath);
+                        return;
+                    }
+                }
+            },
+            _ if inpath.contains('*') || inpath.contains('?') => {
+                let filepath = Path::new(inpath);
+                let dir = match filepath.parent() {
+                    Some(dir) if !dir.as_os_str().is_empty() => dir,
+                    _ => Path::new("."),
+                };
+                let dir_str = dir.to_string_lossy();
+                if dir_str.contains('*') || dir_str.contains('?') {
+                    println!("Wildcards are only supported in file names, not in {}.", dir.display());
+                    return;
+                }
+                if !fs::metadata(dir).map(|m| m.is_dir()).unwrap_or(false) {
+                    println!("{} isn't a directory! aborting.", dir.display());
+                    return;
+                }
+                let pattern = filepath.file_name().unwrap().to_string_lossy().into_owned();
+                PathOrStdin::Dir(dir, Some(pattern))
+            },
+            _ if fs::metadata(inpath).map(|m| m.is_dir()).unwrap_or(false) => {
+                PathOrStdin::Dir(Path::new(inpath), None)
+            },
+            _ => {
+                let filepath = Path::new(inpath);
+                if fs::metadata(filepath).is_err() {
+                    println!("{} doesn't exist! aborting.", filepath.display());
+                    return;
+                }
+                PathOrStdin::Path(filepath)
+            },
+        };
+        // With a single source, there's no need to tell sites apart.
+        let site = match (site, &source) {
+            (Some(_), &PathOrStdin::Dir(dir, _)) => {
+                println!("Files in {} are named after their file name, they can't be given a site name.", dir.display());
+                return;
+            },
+            // Each file is a site named after it
+            (None, &PathOrStdin::Dir(_, _)) => String::new(),
+            // Each message is for the site its sender tells
+            (None, &PathOrStdin::Syslog(_)) => String::new(),
+            (Some(site), _) => site.to_string(),
+            (None, _) if !several_sources => String::new(),
+            (None, &PathOrStdin::Stdin) => "stdin".to_string(),
+            (None, &PathOrStdin::Path(filepath)) => site_name_from_path(filepath),
+        };
+        sources.push((site, source));
+    }
+    let (input_tx, input_rx, dropped_lines) = input_channel();
+    for (site, source) in sources.into_iter() {
+        match source {
+            PathOrStdin::Stdin => spawn_stdin_reader(site, input_tx.clone()),
+            PathOrStdin::Path(filepath) => {
+                // Backfilling by time means reading times with the site's own format.
+                let backfill = match (backfill.clone(), site_parsers.get(&site)) {
+                    (Backfill::Since(since, _), Some(parser)) => Backfill::Since(since, Some(parser.clone())),
+                    (backfill, _) => backfill,
+                };
+                spawn_file_follower(filepath, site, backfill, input_tx.clone())
+            },
+            PathOrStdin::Dir(dir, pattern) => {
+                spawn_dir_follower(dir, pattern, backfill.clone(), input_tx.clone())
+            },
+            PathOrStdin::Syslog(addr) => {
+                let site = if site.is_empty() { None } else { Some(site) };
+                if let Err(e) = spawn_syslog_listener(&addr, site, input_tx.clone()) {
+                    println!("Can't listen for syslog messages! Error: {}", e);
+                    return;
+                }
+            },
+        };
+    }
+    let mut command_sources: Vec<CommandSource> = Vec::new();
+    for arg in commands.iter() {
+        let (site, command) = split_site_arg(arg);
+        // Named after the program it runs
+        let site = match site {
+            Some(site) => site.to_string(),
+            None if !several_sources => String::new(),
+            None => {
+                let program = command.split_whitespace().next().unwrap_or("");
+                site_name_from_path(Path::new(program))
+            },
+        };
+        command_sources.push(CommandSource::spawn(command, site, input_tx.clone()));
+    }
     if unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 {
         println!("STDIN is not a terminal. Trying to get in touch with a terminal now...");
         let tty_fp = unsafe { libc::fopen(
@@ -280,21 +906,20 @@ fn main()
     noecho();
     curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
 
-    let mut wt = WholeThing::new(path);
+    let time_display = if matches.opt_present("u") { TimeDisplay::UTC } else { TimeDisplay::Local };
+    let mut wt = WholeThing::new(input_rx, dropped_lines, parser, &format_name, time_display);
+    wt.visit_stats.set_path_with_query(matches.opt_present("path-query"));
+    wt.replay = replay;
+    wt.site_parsers = site_parsers;
+    wt.sampling = matches.opt_present("sample");
     let last_input = wt.mainloop();
 
     endwin();
-    println!("Program ended with last input {}", last_input);
-    match path {
-        PathOrStdin::Stdin(_) => {
-            match stdin_stopped_rx.try_recv() {
-                Err(_) => {
-                    println!("STDIN still active, process stalled. Press CTRL-C to end it.");
-                }
-                _ => {},
-            }
-        },
-        _ => {},
+    for command_source in command_sources.iter() {
+        command_source.stop();
     }
+    println!("Program ended with last input {}", last_input);
+    // Our input threads might be blocked reading STDIN or a socket. We don't wait for them.
+    process::exit(0);
 }
 